@@ -0,0 +1,14 @@
+; Draws "C8" in the middle of the screen with the built-in font, then waits forever.
+; `chiprust-emu-cli examples/c8-logo.asm` runs it, the built-in rom overrides know it by the SHA-1 of the assembled rom.
+        CLS
+        LD V0, 0xC
+        LD F, V0
+        LD V1, 26
+        LD V2, 13
+        DRW V1, V2, 5
+        LD V0, 8
+        LD F, V0
+        ADD V1, 6
+        DRW V1, V2, 5
+end:
+        JP end
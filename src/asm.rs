@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::fmt;
+
+const PROGRAM_START: i64 = 0x200;
+const MEMORY_SIZE: i64 = 4096;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// a sum of numbers and symbols, resolved once all labels are known
+#[derive(Clone, Debug)]
+struct Value {
+    terms: Vec<(bool, Term)>,
+}
+
+#[derive(Clone, Debug)]
+enum Term {
+    Num(i64),
+    Symbol(String),
+}
+
+#[derive(Clone, Debug)]
+enum Arg {
+    V(u8),
+    // `I`, `DT`, `ST`, `K`, `F`, `HF`, `B`, `[I]` and `R`, uppercased
+    Named(String),
+    Long(Value),
+    Number(Value),
+}
+
+enum Statement {
+    Instruction(String, Vec<Arg>),
+    Bytes(Vec<Value>),
+    Words(Vec<Value>),
+}
+
+struct Item {
+    line: usize,
+    addr: i64,
+    statement: Statement,
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    let lower = s.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn is_symbol(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+    let mut terms = Vec::new();
+    let mut negative = false;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err("expected a value".to_string());
+    }
+    loop {
+        if let Some(r) = rest.strip_prefix('-') {
+            negative = !negative;
+            rest = r.trim_start();
+            continue;
+        }
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let word = rest[..end].trim();
+        let term = if let Some(n) = parse_number(word) {
+            Term::Num(n)
+        } else if is_symbol(word) {
+            Term::Symbol(word.to_string())
+        } else {
+            return Err(format!("invalid value `{}`", word));
+        };
+        terms.push((negative, term));
+        rest = &rest[end..];
+        match rest.chars().next() {
+            Some('+') => negative = false,
+            Some('-') => negative = true,
+            _ => break,
+        }
+        rest = rest[1..].trim_start();
+    }
+    Ok(Value { terms })
+}
+
+fn parse_arg(s: &str) -> Result<Arg, String> {
+    let upper = s.to_uppercase();
+    if upper.len() == 2 && upper.starts_with('V') {
+        if let Ok(x) = u8::from_str_radix(&upper[1..], 16) {
+            return Ok(Arg::V(x));
+        }
+    }
+    if matches!(upper.as_str(), "I" | "DT" | "ST" | "K" | "F" | "HF" | "B" | "[I]" | "R") {
+        return Ok(Arg::Named(upper));
+    }
+    if upper.starts_with("LONG ") {
+        return Ok(Arg::Long(parse_value(&s[5..])?));
+    }
+    Ok(Arg::Number(parse_value(s)?))
+}
+
+fn split_args(s: &str) -> Vec<&str> {
+    if s.trim().is_empty() {
+        Vec::new()
+    } else {
+        s.split(',').map(|arg| arg.trim()).collect()
+    }
+}
+
+struct Assembler {
+    symbols: HashMap<String, i64>,
+    items: Vec<Item>,
+    errors: Vec<AsmError>,
+    addr: i64,
+}
+
+impl Assembler {
+    fn error(&mut self, line: usize, message: String) {
+        self.errors.push(AsmError { line, message });
+    }
+
+    fn define(&mut self, line: usize, name: &str, value: i64) {
+        if !is_symbol(name) || parse_arg(name).map(|arg| !matches!(arg, Arg::Number(_))).unwrap_or(true) {
+            self.error(line, format!("invalid symbol name `{}`", name));
+        } else if self.symbols.insert(name.to_string(), value).is_some() {
+            self.error(line, format!("`{}` is defined twice", name));
+        }
+    }
+
+    fn resolve(&self, value: &Value) -> Result<i64, String> {
+        let mut result = 0i64;
+        for (negative, term) in &value.terms {
+            let v = match term {
+                Term::Num(n) => *n,
+                Term::Symbol(name) => *self
+                    .symbols
+                    .get(name)
+                    .ok_or_else(|| format!("unknown symbol `{}`", name))?,
+            };
+            result = if *negative { result - v } else { result + v };
+        }
+        Ok(result)
+    }
+
+    // first pass, finds the addresses of all labels
+    fn parse_line(&mut self, line: usize, text: &str) {
+        let mut text = match text.find(';') {
+            Some(i) => &text[..i],
+            None => text,
+        }
+        .trim();
+        if let Some(i) = text.find(':') {
+            let label = text[..i].trim();
+            let addr = self.addr;
+            self.define(line, label, addr);
+            text = text[i + 1..].trim();
+        }
+        if text.is_empty() {
+            return;
+        }
+        let (word, rest) = match text.find(char::is_whitespace) {
+            Some(i) => (&text[..i], text[i..].trim()),
+            None => (text, ""),
+        };
+        let keyword = word.to_lowercase();
+
+        // `NAME equ value`
+        if let Some(value) = rest.strip_prefix("equ ").or_else(|| rest.strip_prefix("EQU ")) {
+            match parse_value(value).and_then(|v| self.resolve(&v)) {
+                Ok(v) => self.define(line, word, v),
+                Err(e) => self.error(line, e),
+            }
+            return;
+        }
+
+        let statement = match keyword.as_str() {
+            "org" => {
+                match parse_value(rest).and_then(|v| self.resolve(&v)) {
+                    Ok(v) if (PROGRAM_START..MEMORY_SIZE).contains(&v) => self.addr = v,
+                    Ok(v) => self.error(line, format!("org 0x{:X} is outside of the program memory", v)),
+                    Err(e) => self.error(line, e),
+                }
+                return;
+            }
+            "db" | "dw" => {
+                let values: Result<Vec<Value>, String> = split_args(rest).into_iter().map(parse_value).collect();
+                match values {
+                    Ok(values) if keyword == "db" => Statement::Bytes(values),
+                    Ok(values) => Statement::Words(values),
+                    Err(e) => return self.error(line, e),
+                }
+            }
+            _ => {
+                let args: Result<Vec<Arg>, String> = split_args(rest).into_iter().map(parse_arg).collect();
+                match args {
+                    Ok(args) => Statement::Instruction(word.to_uppercase(), args),
+                    Err(e) => return self.error(line, e),
+                }
+            }
+        };
+        let len = match &statement {
+            Statement::Bytes(values) => values.len() as i64,
+            Statement::Words(values) => values.len() as i64 * 2,
+            Statement::Instruction(_, args) if args.iter().any(|arg| matches!(arg, Arg::Long(_))) => 4,
+            Statement::Instruction(..) => 2,
+        };
+        self.items.push(Item {
+            line,
+            addr: self.addr,
+            statement,
+        });
+        self.addr += len;
+    }
+
+    fn range(&self, value: &Value, min: i64, max: i64, what: &str) -> Result<u16, String> {
+        let v = self.resolve(value)?;
+        if v < min || v > max {
+            return Err(format!("{} {} is out of range", what, v));
+        }
+        // negative bytes are written in two's complement
+        Ok((v & max) as u16)
+    }
+
+    fn encode(&self, mnemonic: &str, args: &[Arg]) -> Result<Vec<u8>, String> {
+        use Arg::*;
+
+        let addr = |v: &Value| self.range(v, 0, 0xFFF, "address");
+        let byte = |v: &Value| self.range(v, -0x80, 0xFF, "byte");
+        let nibble = |v: &Value| self.range(v, 0, 0xF, "nibble");
+        let xy = |x: &u8, y: &u8| ((*x as u16) << 8) | ((*y as u16) << 4);
+        let x = |x: &u8| (*x as u16) << 8;
+        let named = |arg: &Arg, name: &str| matches!(arg, Named(n) if n == name);
+
+        let opcode = match (mnemonic, args) {
+            ("CLS", []) => 0x00E0,
+            ("RET", []) => 0x00EE,
+            ("SCR", []) => 0x00FB,
+            ("SCL", []) => 0x00FC,
+            ("EXIT", []) => 0x00FD,
+            ("LOW", []) => 0x00FE,
+            ("HIGH", []) => 0x00FF,
+            ("AUDIO", []) => 0xF002,
+            ("SCD", [Number(n)]) => 0x00C0 | nibble(n)?,
+            ("SCU", [Number(n)]) => 0x00D0 | nibble(n)?,
+            ("SYS", [Number(a)]) => addr(a)?,
+            ("JP", [Number(a)]) => 0x1000 | addr(a)?,
+            ("JP", [V(0), Number(a)]) => 0xB000 | addr(a)?,
+            ("CALL", [Number(a)]) => 0x2000 | addr(a)?,
+            ("SE", [V(vx), Number(b)]) => 0x3000 | x(vx) | byte(b)?,
+            ("SNE", [V(vx), Number(b)]) => 0x4000 | x(vx) | byte(b)?,
+            ("SE", [V(vx), V(vy)]) => 0x5000 | xy(vx, vy),
+            ("SAVE", [V(vx), V(vy)]) => 0x5002 | xy(vx, vy),
+            ("LOAD", [V(vx), V(vy)]) => 0x5003 | xy(vx, vy),
+            ("LD", [V(vx), Number(b)]) => 0x6000 | x(vx) | byte(b)?,
+            ("ADD", [V(vx), Number(b)]) => 0x7000 | x(vx) | byte(b)?,
+            ("LD", [V(vx), V(vy)]) => 0x8000 | xy(vx, vy),
+            ("OR", [V(vx), V(vy)]) => 0x8001 | xy(vx, vy),
+            ("AND", [V(vx), V(vy)]) => 0x8002 | xy(vx, vy),
+            ("XOR", [V(vx), V(vy)]) => 0x8003 | xy(vx, vy),
+            ("ADD", [V(vx), V(vy)]) => 0x8004 | xy(vx, vy),
+            ("SUB", [V(vx), V(vy)]) => 0x8005 | xy(vx, vy),
+            ("SHR", [V(vx), V(vy)]) => 0x8006 | xy(vx, vy),
+            ("SUBN", [V(vx), V(vy)]) => 0x8007 | xy(vx, vy),
+            ("SHL", [V(vx), V(vy)]) => 0x800E | xy(vx, vy),
+            ("SNE", [V(vx), V(vy)]) => 0x9000 | xy(vx, vy),
+            ("LD", [i, Number(a)]) if named(i, "I") => 0xA000 | addr(a)?,
+            ("LD", [i, Long(a)]) if named(i, "I") => {
+                let a = self.range(a, 0, 0xFFFF, "address")?;
+                return Ok(vec![0xF0, 0x00, (a >> 8) as u8, a as u8]);
+            }
+            ("RND", [V(vx), Number(b)]) => 0xC000 | x(vx) | byte(b)?,
+            ("DRW", [V(vx), V(vy), Number(n)]) => 0xD000 | xy(vx, vy) | nibble(n)?,
+            ("SKP", [V(vx)]) => 0xE09E | x(vx),
+            ("SKNP", [V(vx)]) => 0xE0A1 | x(vx),
+            ("PLANE", [Number(n)]) => 0xF001 | (nibble(n)? << 8),
+            ("PITCH", [V(vx)]) => 0xF03A | x(vx),
+            ("LD", [V(vx), dt]) if named(dt, "DT") => 0xF007 | x(vx),
+            ("LD", [V(vx), k]) if named(k, "K") => 0xF00A | x(vx),
+            ("LD", [dt, V(vx)]) if named(dt, "DT") => 0xF015 | x(vx),
+            ("LD", [st, V(vx)]) if named(st, "ST") => 0xF018 | x(vx),
+            ("ADD", [i, V(vx)]) if named(i, "I") => 0xF01E | x(vx),
+            ("LD", [f, V(vx)]) if named(f, "F") => 0xF029 | x(vx),
+            ("LD", [hf, V(vx)]) if named(hf, "HF") => 0xF030 | x(vx),
+            ("LD", [b, V(vx)]) if named(b, "B") => 0xF033 | x(vx),
+            ("LD", [i, V(vx)]) if named(i, "[I]") => 0xF055 | x(vx),
+            ("LD", [V(vx), i]) if named(i, "[I]") => 0xF065 | x(vx),
+            ("LD", [r, V(vx)]) if named(r, "R") => 0xF075 | x(vx),
+            ("LD", [V(vx), r]) if named(r, "R") => 0xF085 | x(vx),
+            _ => return Err(format!("invalid instruction `{}` with {} operand(s)", mnemonic, args.len())),
+        };
+        Ok(vec![(opcode >> 8) as u8, opcode as u8])
+    }
+}
+
+/// Assembles the source into a rom loaded at 0x200.
+///
+/// The syntax is the one `disasm` writes: one instruction per line, `label:`,
+/// `NAME equ value`, `org`, `db`/`dw` and `;` comments.
+pub fn assemble(source: &str) -> Result<Vec<u8>, Vec<AsmError>> {
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        items: Vec::new(),
+        errors: Vec::new(),
+        addr: PROGRAM_START,
+    };
+    for (i, text) in source.lines().enumerate() {
+        assembler.parse_line(i + 1, text);
+    }
+
+    let mut rom = Vec::new();
+    let mut errors = Vec::new();
+    for item in &assembler.items {
+        let bytes = match &item.statement {
+            Statement::Instruction(mnemonic, args) => assembler.encode(mnemonic, args),
+            Statement::Bytes(values) => values
+                .iter()
+                .map(|v| assembler.range(v, -0x80, 0xFF, "byte").map(|b| b as u8))
+                .collect(),
+            Statement::Words(values) => values
+                .iter()
+                .map(|v| assembler.range(v, -0x8000, 0xFFFF, "word"))
+                .collect::<Result<Vec<u16>, String>>()
+                .map(|words| words.iter().flat_map(|w| vec![(w >> 8) as u8, *w as u8]).collect()),
+        };
+        let bytes = match bytes {
+            Ok(v) => v,
+            Err(message) => {
+                errors.push(AsmError { line: item.line, message });
+                continue;
+            }
+        };
+        let start = (item.addr - PROGRAM_START) as usize;
+        if item.addr + bytes.len() as i64 > MEMORY_SIZE {
+            errors.push(AsmError {
+                line: item.line,
+                message: "program doesn't fit into the memory".to_string(),
+            });
+            continue;
+        }
+        if rom.len() < start + bytes.len() {
+            rom.resize(start + bytes.len(), 0);
+        }
+        rom[start..start + bytes.len()].copy_from_slice(&bytes);
+    }
+
+    let mut all_errors = assembler.errors;
+    all_errors.extend(errors);
+    if !all_errors.is_empty() {
+        all_errors.sort_by_key(|e| e.line);
+        return Err(all_errors);
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::{decode, listing};
+
+    #[test]
+    fn resolves_labels_and_constants() {
+        let source = "
+            SPEED equ 3
+            start:
+                LD V0, SPEED + 1 ; comment
+                JP start
+            sprite: db 0xF0, -1
+            dw sprite
+        ";
+        assert_eq!(assemble(source).unwrap(), vec![0x60, 0x04, 0x12, 0x00, 0xF0, 0xFF, 0x02, 0x04]);
+    }
+
+    #[test]
+    fn reports_errors_by_line() {
+        let errors = assemble("CLS\nJP nowhere\nLD V0, 256\nstart:\nstart:").unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![2, 3, 5]);
+        assert_eq!(errors[0].message, "unknown symbol `nowhere`");
+    }
+
+    #[test]
+    fn reassembles_every_decoded_opcode() {
+        for opcode in 0..=0xFFFFu32 {
+            let mem = [(opcode >> 8) as u8, opcode as u8, 0x12, 0x34];
+            if let Some(instruction) = decode(&mem, 0) {
+                assert_eq!(assemble(&instruction.to_string()).unwrap(), &mem[..instruction.len], "{}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn reassembles_a_listing() {
+        let source = "
+                CLS
+                LD I, sprite
+                LD V0, 0
+            loop:
+                DRW V0, V0, 2
+                CALL wait
+                ADD V0, 1
+                SE V0, 8
+                JP loop
+                EXIT
+            wait:
+                LD V1, 0x10
+                LD DT, V1
+                RET
+            sprite:
+                db 0x81, 0x42, 0x00
+        ";
+        let rom = assemble(source).unwrap();
+        let listing = listing(&rom, "test.ch8");
+        assert_eq!(assemble(&listing).unwrap(), rom, "{}", listing);
+        assert!(listing.contains("CALL sub_"), "{}", listing);
+        assert!(listing.contains("db 0x81, 0x42, 0x00"), "{}", listing);
+    }
+}
@@ -0,0 +1,452 @@
+#[cfg(feature = "sound")]
+mod ring;
+
+use chiprust_emu::Chip8;
+#[cfg(feature = "sound")]
+use lazy_static::lazy_static;
+#[cfg(feature = "sound")]
+use rodio::Source;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+#[cfg(feature = "sound")]
+use std::time::Duration;
+
+use crate::cpu::Cpu;
+#[cfg(feature = "sound")]
+use ring::VoiceRing;
+
+pub const SAMPLE_RATE: u32 = 44100;
+// sound fades in and out over this many samples instead of clicking, about 5 ms
+const ENVELOPE: f64 = 220.;
+
+pub const WAVEFORMS: &[&str] = &["sine", "square", "triangle", "sawtooth", "noise"];
+
+// how often the wav header is updated, a tenth of a second
+const HEADER_INTERVAL: u32 = SAMPLE_RATE / 10;
+
+// timer ticks queued before the audio device starts playing them, 50 ms at 60 Hz
+#[cfg(feature = "sound")]
+const LATENCY: usize = 3;
+
+#[cfg(feature = "sound")]
+lazy_static!(
+    static ref VOICES: VoiceRing = VoiceRing::new();
+);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Triangle,
+    Sawtooth,
+    Noise,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "sine" => Some(Waveform::Sine),
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            "noise" => Some(Waveform::Noise),
+            _ => None,
+        }
+    }
+}
+
+/// The beep of roms without an audio pattern, and the volume of all sound.
+#[derive(Clone, Copy)]
+pub struct Tone {
+    pub freq: u32,
+    pub waveform: Waveform,
+    // 0 to 1 of full scale
+    pub volume: f64,
+}
+
+/// What the beeper plays until the next timer tick.
+#[derive(Clone, Copy, Default)]
+pub struct Voice {
+    pub playing: bool,
+    // xo-chip 1 bit samples set by F002, played from the highest bit of the first byte
+    pub pattern: Option<[u8; 16]>,
+    // xo-chip pitch set by FX3A
+    pub pitch: u8,
+}
+
+impl Voice {
+    pub fn of(chip: &Chip8, cpu: &Cpu) -> Voice {
+        Voice {
+            playing: chip.is_sound_playing(),
+            pattern: cpu.pattern,
+            pitch: cpu.pitch,
+        }
+    }
+}
+
+/// Renders voices: roms that never set a pattern get the tone, xo-chip roms their pattern,
+/// looped at the rate given by the pitch.
+pub struct Synth {
+    tone: Tone,
+    // position in the tone's wave, in periods
+    phase: f64,
+    // position in the pattern, in bits
+    position: f64,
+    // envelope, follows whether the voice is playing
+    gain: f64,
+    // 15 bit lfsr, a new noise level is taken from it every period
+    lfsr: u16,
+}
+
+impl Synth {
+    pub fn new(tone: Tone) -> Synth {
+        Synth {
+            tone,
+            phase: 0.,
+            position: 0.,
+            gain: 0.,
+            lfsr: 1,
+        }
+    }
+
+    // the tone's wave at the current phase, from -1 to 1
+    fn wave(&self) -> f64 {
+        let phase = self.phase;
+        match self.tone.waveform {
+            Waveform::Sine => (phase * 2. * PI).sin(),
+            Waveform::Square => if phase < 0.5 { 1. } else { -1. },
+            Waveform::Triangle => 1. - 4. * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2. * phase - 1.,
+            Waveform::Noise => if self.lfsr & 1 != 0 { 1. } else { -1. },
+        }
+    }
+
+    pub fn sample(&mut self, voice: &Voice) -> i16 {
+        let level = match &voice.pattern {
+            None => {
+                let level = self.wave();
+                self.phase += self.tone.freq as f64 / SAMPLE_RATE as f64;
+                if self.phase >= 1. {
+                    self.phase %= 1.;
+                    let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+                    self.lfsr = (self.lfsr >> 1) | (bit << 14);
+                }
+                level
+            }
+            Some(pattern) => {
+                let bit = self.position as usize;
+                let level = if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1. } else { -1. };
+                // 4000 bits per second at pitch 64, an octave every 48 steps
+                let rate = 4000. * 2f64.powf((voice.pitch as f64 - 64.) / 48.);
+                self.position = (self.position + rate / SAMPLE_RATE as f64) % 128.;
+                level
+            }
+        };
+        let target = if voice.playing { 1. } else { 0. };
+        if self.gain < target {
+            self.gain = (self.gain + 1. / ENVELOPE).min(target);
+        } else {
+            self.gain = (self.gain - 1. / ENVELOPE).max(target);
+        }
+        (level * self.gain * self.tone.volume * i16::MAX as f64) as i16
+    }
+}
+
+/// The synth rendered one timer tick at a time, for files that follow emulated time.
+pub struct Beeper {
+    synth: Synth,
+    timers_freq: u64,
+    ticks: u64,
+}
+
+impl Beeper {
+    pub fn new(tone: Tone, timers_freq: u32) -> Beeper {
+        Beeper {
+            synth: Synth::new(tone),
+            // unlimited timers still tick 60 times per emulated second
+            timers_freq: match timers_freq {
+                0 => 60,
+                v => v as u64,
+            },
+            ticks: 0,
+        }
+    }
+
+    // number of samples from this timer tick until the next one
+    fn tick_len(&mut self) -> u64 {
+        // counted from the start, so ticks don't drift from the sample rate
+        let rate = SAMPLE_RATE as u64;
+        let count = (self.ticks + 1) * rate / self.timers_freq - self.ticks * rate / self.timers_freq;
+        self.ticks += 1;
+        count
+    }
+
+    /// Samples from this timer tick until the next one.
+    pub fn tick(&mut self, voice: &Voice) -> Vec<i16> {
+        let count = self.tick_len();
+        (0..count).map(|_| self.synth.sample(voice)).collect()
+    }
+}
+
+/// The beeper as a rodio source, playing every voice pushed with `push_voice` for exactly one tick.
+/// Playback starts once `LATENCY` ticks are queued, so late timer ticks don't change how long beeps are.
+#[cfg(feature = "sound")]
+pub struct SynthSource {
+    beeper: Beeper,
+    voice: Voice,
+    // samples left of the current tick
+    left: u64,
+    // waiting for the queue to fill up, at the start and after it ran dry
+    buffering: bool,
+}
+
+#[cfg(feature = "sound")]
+impl SynthSource {
+    pub fn new(tone: Tone, timers_freq: u32) -> SynthSource {
+        SynthSource {
+            beeper: Beeper::new(tone, timers_freq),
+            voice: Voice::default(),
+            left: 0,
+            buffering: true,
+        }
+    }
+}
+
+#[cfg(feature = "sound")]
+impl Iterator for SynthSource {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.left == 0 {
+            // drops ticks the device fell behind on, so the latency stays the same
+            while VOICES.len() > 2 * LATENCY {
+                VOICES.pop();
+            }
+            if self.buffering && VOICES.len() >= LATENCY {
+                self.buffering = false;
+            }
+            match if self.buffering { None } else { VOICES.pop() } {
+                Some(voice) => {
+                    self.voice = voice;
+                    self.left = self.beeper.tick_len();
+                }
+                // no ticks, e.g. while paused: fade out and wait for the queue to fill up again
+                None => {
+                    self.buffering = true;
+                    self.voice.playing = false;
+                    self.left = 1;
+                }
+            }
+        }
+        self.left -= 1;
+        Some(self.beeper.synth.sample(&self.voice))
+    }
+}
+
+#[cfg(feature = "sound")]
+impl Source for SynthSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Queues what the audio device plays for the next tick, call after every timer tick.
+#[cfg(feature = "sound")]
+pub fn push_voice(voice: &Voice) {
+    // only fails if the device stopped taking voices, then there's nobody to hear them anyway
+    VOICES.push(voice);
+}
+
+/// 16 bit mono wav file. The header is brought up to date every `HEADER_INTERVAL` samples and when the writer is dropped,
+/// so a file cut short by the emulator exiting is still complete, only missing its last moments.
+pub struct WavWriter {
+    path: String,
+    file: BufWriter<File>,
+    samples: u32,
+    // samples the header says the file has
+    header_samples: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &str) -> Result<WavWriter, String> {
+        let file = File::create(path).map_err(|e| format!("Can't create {}: {}", path, e))?;
+        let mut writer = WavWriter {
+            path: path.to_string(),
+            file: BufWriter::new(file),
+            samples: 0,
+            header_samples: 0,
+        };
+        writer.write_header().map_err(|e| writer.error(e))?;
+        Ok(writer)
+    }
+
+    fn error(&self, e: std::io::Error) -> String {
+        format!("Can't write {}: {}", self.path, e)
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let data_len = self.samples * 2;
+        let header = [
+            &b"RIFF"[..],
+            &(36 + data_len).to_le_bytes(),
+            b"WAVEfmt ",
+            &16u32.to_le_bytes(),
+            // pcm, one channel
+            &1u16.to_le_bytes(),
+            &1u16.to_le_bytes(),
+            &SAMPLE_RATE.to_le_bytes(),
+            // bytes per second, bytes per sample, bits per sample
+            &(SAMPLE_RATE * 2).to_le_bytes(),
+            &2u16.to_le_bytes(),
+            &16u16.to_le_bytes(),
+            b"data",
+            &data_len.to_le_bytes(),
+        ]
+        .concat();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.flush()?;
+        self.header_samples = self.samples;
+        Ok(())
+    }
+
+    pub fn write(&mut self, samples: &[i16]) -> Result<(), String> {
+        let bytes: Vec<u8> = samples.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+        self.samples += samples.len() as u32;
+        let mut result = self.file.write_all(&bytes);
+        if result.is_ok() && self.samples - self.header_samples >= HEADER_INTERVAL {
+            result = self.write_header();
+        }
+        result.map_err(|e| self.error(e))
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if self.header_samples != self.samples {
+            // nobody to tell about errors anymore
+            let _ = self.write_header();
+        }
+    }
+}
+
+/// Writes the beeper to a wav file, a timer tick at a time.
+pub struct AudioOut {
+    beeper: Beeper,
+    wav: WavWriter,
+}
+
+impl AudioOut {
+    pub fn create(path: &str, tone: Tone, timers_freq: u32) -> Result<AudioOut, String> {
+        Ok(AudioOut {
+            beeper: Beeper::new(tone, timers_freq),
+            wav: WavWriter::create(path)?,
+        })
+    }
+
+    /// Call after every timer tick with what the beeper plays now.
+    pub fn tick(&mut self, voice: &Voice) -> Result<(), String> {
+        let samples = self.beeper.tick(voice);
+        self.wav.write(&samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const TONE: Tone = Tone { freq: 440, waveform: Waveform::Square, volume: 0.5 };
+
+    fn temp_path() -> String {
+        let path = std::env::temp_dir().join(format!("chiprust-emu-cli-{}.wav", std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    fn le32(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    #[test]
+    fn ticks_add_up_to_the_sample_rate() {
+        for &freq in &[60, 50, 7] {
+            let mut beeper = Beeper::new(TONE, freq);
+            let total: usize = (0..freq).map(|_| beeper.tick(&Voice::default()).len()).sum();
+            assert_eq!(total, SAMPLE_RATE as usize, "{} Hz", freq);
+        }
+    }
+
+    #[test]
+    fn fades_in_and_out() {
+        let mut synth = Synth::new(TONE);
+        let playing = Voice { playing: true, ..Voice::default() };
+        let first: Vec<i16> = (0..ENVELOPE as usize * 2).map(|_| synth.sample(&playing)).collect();
+        assert!(first[0].abs() < 100);
+        assert_eq!(first[first.len() - 1].abs(), (0.5 * i16::MAX as f64) as i16);
+        let last: Vec<i16> = (0..ENVELOPE as usize).map(|_| synth.sample(&Voice::default())).collect();
+        assert_eq!(last[last.len() - 1], 0);
+    }
+
+    #[test]
+    fn plays_the_pattern_at_the_pitch() {
+        // rising edges in a tenth of a second
+        let pulses = |voice: &Voice| {
+            let mut synth = Synth::new(TONE);
+            // starting from silence, the first pulse rises at the first sample
+            let samples: Vec<i16> = std::iter::once(0)
+                .chain((0..SAMPLE_RATE as usize / 10).map(|_| synth.sample(voice)))
+                .collect();
+            samples.windows(2).filter(|v| v[0] <= 0 && v[1] > 0).count()
+        };
+        // one bit set in every 8, 4000 bits per second give a pulse every 2 ms
+        let voice = Voice { playing: true, pattern: Some([0x80; 16]), pitch: 64 };
+        assert_eq!(pulses(&voice), 50);
+        assert_eq!(pulses(&Voice { pitch: 112, ..voice }), 100);
+    }
+
+    #[test]
+    fn wav_header_is_complete_once_dropped() {
+        let path = temp_path();
+        let mut wav = WavWriter::create(&path).unwrap();
+        wav.write(&[1, -2, 3]).unwrap();
+        // the header isn't rewritten for every few samples
+        assert_eq!(le32(&fs::read(&path).unwrap(), 40), 0);
+        drop(wav);
+
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(le32(&bytes, 4), 36 + 6);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(le32(&bytes, 24), SAMPLE_RATE);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(le32(&bytes, 40), 6);
+        assert_eq!(&bytes[44..], [1, 0, 0xfe, 0xff, 3, 0]);
+    }
+
+    #[test]
+    fn wav_header_follows_long_writes() {
+        let path = temp_path() + "-long";
+        let mut wav = WavWriter::create(&path).unwrap();
+        wav.write(&vec![0; HEADER_INTERVAL as usize]).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        drop(wav);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(le32(&bytes, 40), HEADER_INTERVAL * 2);
+        assert_eq!(bytes.len(), 44 + HEADER_INTERVAL as usize * 2);
+    }
+}
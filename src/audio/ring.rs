@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use super::Voice;
+
+// about half a second of timer ticks at 60 Hz
+const CAPACITY: usize = 32;
+
+/// Queue of voices, one per timer tick, from the timers thread to the audio device.
+/// There must be only one thread pushing and one popping; neither ever waits for the other,
+/// as every voice is packed into atomics.
+pub struct VoiceRing {
+    // pattern bytes 0-7 and 8-15, then flags and pitch
+    slots: Vec<[AtomicU64; 3]>,
+    // voices pushed and popped so far, the difference is the length
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+impl VoiceRing {
+    pub fn new() -> VoiceRing {
+        VoiceRing {
+            slots: (0..CAPACITY).map(|_| Default::default()).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.head.load(Ordering::Acquire).wrapping_sub(self.tail.load(Ordering::Acquire))
+    }
+
+    /// Adds a voice, unless the queue is full because nothing takes them out.
+    pub fn push(&self, voice: &Voice) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        if head.wrapping_sub(self.tail.load(Ordering::Acquire)) == CAPACITY {
+            return false;
+        }
+        let pattern = voice.pattern.unwrap_or([0; 16]);
+        let mut half = [0; 8];
+        let slot = &self.slots[head % CAPACITY];
+        half.copy_from_slice(&pattern[..8]);
+        slot[0].store(u64::from_be_bytes(half), Ordering::Relaxed);
+        half.copy_from_slice(&pattern[8..]);
+        slot[1].store(u64::from_be_bytes(half), Ordering::Relaxed);
+        let flags = voice.playing as u64 | (voice.pattern.is_some() as u64) << 1 | (voice.pitch as u64) << 8;
+        slot[2].store(flags, Ordering::Relaxed);
+        // publishes the slot to the consumer
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    pub fn pop(&self) -> Option<Voice> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if self.head.load(Ordering::Acquire) == tail {
+            return None;
+        }
+        let slot = &self.slots[tail % CAPACITY];
+        let mut pattern = [0; 16];
+        pattern[..8].copy_from_slice(&slot[0].load(Ordering::Relaxed).to_be_bytes());
+        pattern[8..].copy_from_slice(&slot[1].load(Ordering::Relaxed).to_be_bytes());
+        let flags = slot[2].load(Ordering::Relaxed);
+        // hands the slot back to the producer
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(Voice {
+            playing: flags & 1 != 0,
+            pattern: if flags & 2 != 0 { Some(pattern) } else { None },
+            pitch: (flags >> 8) as u8,
+        })
+    }
+}
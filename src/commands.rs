@@ -0,0 +1,58 @@
+use clap::ArgMatches;
+use std::fs;
+use std::path::Path;
+
+use crate::config::{is_assembly, read_assembly, read_program};
+use crate::disasm;
+use crate::regression;
+use crate::rom_overrides;
+use crate::sha1::sha1_hex;
+
+pub fn asm(matches: &ArgMatches) -> Result<(), String> {
+    let source = matches.value_of("source").unwrap();
+    let rom = read_assembly(source)?;
+    let output = match matches.value_of("output") {
+        Some(v) => v.into(),
+        None => Path::new(source).with_extension("ch8"),
+    };
+    fs::write(output, rom).map_err(|e| format!("{}", e))
+}
+
+pub fn disasm(matches: &ArgMatches) -> Result<(), String> {
+    let source = matches.value_of("source").unwrap();
+    let program = read_program(source)?;
+    let name = Path::new(source)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let listing = disasm::listing(&program, &name);
+    match matches.value_of("output") {
+        Some(output) => fs::write(output, listing).map_err(|e| format!("{}", e)),
+        None => {
+            print!("{}", listing);
+            Ok(())
+        }
+    }
+}
+
+pub fn info(matches: &ArgMatches) -> Result<(), String> {
+    let source = matches.value_of("info").unwrap();
+    let program = if is_assembly(source) {
+        read_assembly(source)?
+    } else {
+        read_program(source)?
+    };
+    let sha1 = sha1_hex(&program);
+    println!("file: {}", source);
+    println!("sha1: {}", sha1);
+    println!("size: {} bytes", program.len());
+    match rom_overrides::lookup(&sha1) {
+        Some(overrides) => print!("{}", overrides.describe()),
+        None => println!("no built-in overrides, the default settings are used"),
+    }
+    Ok(())
+}
+
+pub fn test(matches: &ArgMatches) -> Result<(), String> {
+    regression::run_manifest(matches.value_of("manifest").unwrap(), matches.is_present("update"))
+}
@@ -1,14 +1,65 @@
-use clap::{App, Arg, ArgMatches};
+mod profile;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 #[cfg(feature = "sound")]
-use rodio::{source::SineWave, OutputStream, Sink};
+use rodio::{OutputStream, Sink};
 use std::io;
-use std::{fs::File, io::Read};
+use std::ops::Range;
+use crate::debugger::{parse_range, Condition};
+use crate::asm::assemble;
+use crate::input;
+use crate::keymap::Keymap;
+use crate::quirks::{self, Quirks};
+use crate::screenshot::ImageOptions;
+#[cfg(feature = "sound")]
+use crate::audio::SynthSource;
+use crate::audio::{self, Tone, Waveform};
+use crate::sha1::sha1_hex;
+use crate::rom_overrides::{self, RomOverrides};
+use crossterm::style::Color;
+use profile::Profile;
+use std::convert::TryFrom;
+use std::{fs::{self, File}, io::Read, path::Path};
 
-fn get_matches() -> ArgMatches<'static> {
+pub fn get_matches() -> ArgMatches<'static> {
     App::new("ChipRust Emulator CLI")
                               .version("1.0")
                               .author("Maxim K. <maximxlssoff@gmail.com>")
                               .about("CLI for ChipRust Emulator")
+                              .setting(AppSettings::SubcommandsNegateReqs)
+                              .subcommand(SubCommand::with_name("asm")
+                                   .about("Assembles a source in the disasm listing syntax to a rom")
+                                   .arg(Arg::with_name("source")
+                                        .help("Sets the assembly source file")
+                                        .required(true)
+                                        .index(1))
+                                   .arg(Arg::with_name("output")
+                                        .short("o")
+                                        .long("output")
+                                        .value_name("file")
+                                        .help("Sets the rom file to write, defaults to the source with the .ch8 extension")
+                                        .takes_value(true)))
+                              .subcommand(SubCommand::with_name("test")
+                                   .about("Runs the roms of a test manifest headless and compares their screens to the expected ones")
+                                   .arg(Arg::with_name("manifest")
+                                        .help("Sets the manifest file, every [section] of it is a test case")
+                                        .required(true)
+                                        .index(1))
+                                   .arg(Arg::with_name("update")
+                                        .long("update")
+                                        .help("Writes the expected screenshots from the actual screens instead of comparing them")))
+                              .subcommand(SubCommand::with_name("disasm")
+                                   .about("Writes a disassembly listing of a rom")
+                                   .arg(Arg::with_name("source")
+                                        .help("Sets the rom file to disassemble")
+                                        .required(true)
+                                        .index(1))
+                                   .arg(Arg::with_name("output")
+                                        .short("o")
+                                        .long("output")
+                                        .value_name("file")
+                                        .help("Sets the listing file to write, prints to stdout if not set")
+                                        .takes_value(true)))
                               .arg(Arg::with_name("tone")
                                    .short("t")
                                    .long("tone")
@@ -16,11 +67,23 @@ fn get_matches() -> ArgMatches<'static> {
                                    .help("Sets a custom audio tone")
                                    .default_value("900")
                                    .takes_value(true))
+                              .arg(Arg::with_name("waveform")
+                                   .long("waveform")
+                                   .value_name("waveform")
+                                   .help("Sets the waveform of the tone: sine, square, triangle, sawtooth or noise")
+                                   .default_value("sine")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("volume")
+                                   .long("volume")
+                                   .value_name("percent")
+                                   .help("Sets the sound volume, from 0 to 100")
+                                   .default_value("50")
+                                   .takes_value(true))
                               .arg(Arg::with_name("cpu_freq")
                                    .short("c")
                                    .long("cpu")
                                    .value_name("frequency")
-                                   .help("Sets a custom cpu frequency. If zero, instructions would be executed ASAP.")
+                                   .help("Sets a custom cpu frequency. If zero, instructions are executed ASAP, 20000 per frame")
                                    .default_value("60")
                                    .takes_value(true))
                               .arg(Arg::with_name("draw_freq")
@@ -34,39 +97,318 @@ fn get_matches() -> ArgMatches<'static> {
                                    .short("s")
                                    .long("speed")
                                    .value_name("frequency")
-                                   .help("Sets a custom speed (actually timers' tick frequency). If zero, timers would be decremented ASAP.")
+                                   .help("Sets a custom speed (actually timers' tick frequency), the frames per second. If zero, runs in turbo mode")
                                    .default_value("60")
                                    .takes_value(true))
+                              .arg(Arg::with_name("turbo")
+                                   .long("turbo")
+                                   .help("Starts in turbo mode, running frames as fast as possible. The turbo key (Tab) toggles it"))
+                              .arg(Arg::with_name("step_count")
+                                   .long("step-count")
+                                   .value_name("count")
+                                   .help("Sets how many instructions are executed by the \"step many\" key (M) while paused")
+                                   .default_value("10")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("break")
+                                   .short("b")
+                                   .long("break")
+                                   .value_name("address")
+                                   .help("Sets a breakpoint at the address (e.g. 0x2a4). Can be used multiple times")
+                                   .multiple(true)
+                                   .number_of_values(1)
+                                   .takes_value(true))
+                              .arg(Arg::with_name("watch")
+                                   .short("w")
+                                   .long("watch")
+                                   .value_name("range")
+                                   .help("Pauses when a byte in the range (e.g. 0x300..0x310, end exclusive) changes or I points into it. Can be used multiple times")
+                                   .multiple(true)
+                                   .number_of_values(1)
+                                   .takes_value(true))
+                              .arg(Arg::with_name("break_if")
+                                   .long("break-if")
+                                   .value_name("condition")
+                                   .help("Pauses when the condition becomes true, e.g. \"pc == 0x23a && v3 > 0x10\" or \"mem[i] == 0xff\". Can be used multiple times")
+                                   .multiple(true)
+                                   .number_of_values(1)
+                                   .takes_value(true))
+                              .arg(Arg::with_name("load_state")
+                                   .long("load-state")
+                                   .value_name("file")
+                                   .help("Loads a save state after loading the rom. F5/F9 quick save/load to <rom>.state")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("rewind_budget")
+                                   .long("rewind-budget")
+                                   .value_name("MiB")
+                                   .help("Sets how much memory the rewind buffer may use. If zero, rewinding is disabled")
+                                   .default_value("16")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("rewind_interval")
+                                   .long("rewind-interval")
+                                   .value_name("frames")
+                                   .help("Sets how many frames run between rewind snapshots, stepped instructions are recorded one by one")
+                                   .default_value("1")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("record")
+                                   .long("record")
+                                   .value_name("file")
+                                   .help("Records every key query and its answer to a movie file")
+                                   .conflicts_with("replay")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("replay")
+                                   .long("replay")
+                                   .value_name("file")
+                                   .help("Answers key queries from a movie file instead of the keyboard, using its rng seed")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("seed")
+                                   .long("seed")
+                                   .value_name("number")
+                                   .help("Sets the random number generator seed. Random if not set")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("input")
+                                   .long("input")
+                                   .value_name("backend")
+                                   .help("Sets where chip-8 keys are read from. The terminal backend works over ssh, device reads the keyboard directly")
+                                   .possible_values(input::BACKENDS)
+                                   .default_value(input::BACKENDS[0])
+                                   .takes_value(true))
+                              .arg(Arg::with_name("key_hold")
+                                   .long("key-hold")
+                                   .value_name("milliseconds")
+                                   .help("Sets for how long a key counts as held after a press, for terminals that don't report key releases")
+                                   .default_value("200")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("keymap")
+                                   .long("keymap")
+                                   .value_name("file")
+                                   .help("Loads a keymap file remapping the keypad and the hotkeys. <rom>.keymap is loaded after it if it exists")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("platform")
+                                   .long("platform")
+                                   .value_name("platform")
+                                   .help("Sets the quirks to the ones of the platform's original interpreter")
+                                   .possible_values(quirks::PLATFORMS)
+                                   .default_value(quirks::PLATFORMS[0])
+                                   .takes_value(true))
+                              .arg(Arg::with_name("quirks")
+                                   .long("quirks")
+                                   .value_name("list")
+                                   .help("Changes quirks of the platform, e.g. \"shift_vy=off,wrap=on\". Quirks are shift_vy, load_store_i, jump_vx, vf_reset, wrap and display_wait")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("foreground")
+                                   .long("foreground")
+                                   .value_name("color")
+                                   .help("Sets the color of lit pixels, a name like dark_green or #rrggbb")
+                                   .default_value("white")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("background")
+                                   .long("background")
+                                   .value_name("color")
+                                   .help("Sets the color of unlit pixels, a name like black or #rrggbb")
+                                   .default_value("dark_grey")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("print_config")
+                                   .long("print-config")
+                                   .help("Prints the effective configuration, merged from the config file and the arguments, and exits"))
+                              .arg(Arg::with_name("info")
+                                   .long("info")
+                                   .value_name("rom")
+                                   .help("Prints the built-in overrides for a rom and exits")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("headless")
+                                   .long("headless")
+                                   .help("Runs without the terminal ui as fast as possible, until --cycles, --frames, a breakpoint, a watchpoint or a --break-if condition. Exits with 0 when stopped, 1 on an emulation error, 2 if a limit came before an expected break and 3 if the rom waits for a key")
+                                   .conflicts_with("print_config"))
+                              .arg(Arg::with_name("cycles")
+                                   .long("cycles")
+                                   .value_name("count")
+                                   .help("Stops a headless run after this many instructions")
+                                   .requires("headless")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("frames")
+                                   .long("frames")
+                                   .value_name("count")
+                                   .help("Stops a headless run after this many frames")
+                                   .requires("headless")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("screenshot_at_frame")
+                                   .long("screenshot-at-frame")
+                                   .value_names(&["frame", "file"])
+                                   .help("Saves a screenshot after the frame in a headless run, as .png, .pbm or .txt by the extension. Can be used multiple times")
+                                   .requires("headless")
+                                   .multiple(true)
+                                   .number_of_values(2)
+                                   .takes_value(true))
+                              .arg(Arg::with_name("audio_out")
+                                   .long("audio-out")
+                                   .value_name("file")
+                                   .help("Writes the sound to a .wav file, one timer tick at a time, also in headless mode")
+                                   .takes_value(true))
+                              .arg(Arg::with_name("screenshot_scale")
+                                   .long("screenshot-scale")
+                                   .value_name("scale")
+                                   .help("Sets how many png pixels every screen pixel becomes, the colors are --foreground and --background")
+                                   .default_value("4")
+                                   .takes_value(true))
                               .arg(Arg::with_name("debug")
-                                   .short("d")
+                                   .short("D")
                                    .long("debug")
                                    .takes_value(false)
                                    .help("Add this flag to print executed instructions"))
                               .arg(Arg::with_name("source")
-                                   .help("Sets the rom file to execute")
-                                   .required(true)
+                                   .help("Sets the rom file to execute. Files with .8o, .asm or .s extension are assembled first")
+                                   .required_unless("info")
                                    .index(1))
                               .get_matches()
 }
 
+/// Reads a rom file, checking that it fits into the memory after 0x200.
+pub fn read_program(path: &str) -> Result<Vec<u8>, String> {
+    let mut f = match File::open(path) {
+        io::Result::Ok(f) => f,
+        io::Result::Err(e) => return Err(format!("{}", e)),
+    };
+
+    let mut buf: Vec<u8> = Vec::with_capacity(3583);
+
+    match f.read_to_end(&mut buf) {
+        Ok(v) => {
+            if v >= 3583 {
+                return Err(format!("Source file is too big! Got {} bytes, while free memory is only 3583 bytes.", v));
+            }
+        }
+        Err(e) => return Err(format!("{}", e)),
+    }
+
+    Ok(buf)
+}
+
+/// Whether the file is an assembly source rather than a rom, by its extension.
+pub fn is_assembly(path: &str) -> bool {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some(e) => matches!(e.to_lowercase().as_str(), "8o" | "asm" | "s"),
+        None => false,
+    }
+}
+
+/// Reads and assembles an assembly source, formatting errors with the file name.
+pub fn read_assembly(path: &str) -> Result<Vec<u8>, String> {
+    let source = match fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("{}", e)),
+    };
+    match assemble(&source) {
+        Ok(v) => Ok(v),
+        Err(errors) => Err(errors
+            .iter()
+            .map(|e| format!("{}:{}: {}", path, e.line, e.message))
+            .collect::<Vec<String>>()
+            .join("\n")),
+    }
+}
+
+/// Parses an address written as `0x2a4`, `$2a4` or decimal `676`.
+pub fn parse_address(s: &str) -> Result<usize, String> {
+    parse_address_below(s, 4096)
+}
+
+/// Parses the exclusive end of a memory range, which may be one past the last address.
+pub fn parse_end_address(s: &str) -> Result<usize, String> {
+    parse_address_below(s, 4097)
+}
+
+fn parse_address_below(s: &str, bound: usize) -> Result<usize, String> {
+    let s = s.trim();
+    let result = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix('$')) {
+        usize::from_str_radix(hex, 16)
+    } else {
+        s.parse::<usize>()
+    };
+    match result {
+        Ok(v) if v < bound => Ok(v),
+        Ok(v) => Err(format!("Address {:#x} is out of memory bounds.", v)),
+        Err(_) => Err(format!("Can't parse {} to an address.", s)),
+    }
+}
+
+/// Parses a color written as a crossterm color name, e.g. `dark_green`, or as `#rrggbb`.
+pub fn parse_color(s: &str) -> Result<Color, String> {
+    if let Some(hex) = s.strip_prefix('#') {
+        if let (6, Ok(v)) = (hex.len(), u32::from_str_radix(hex, 16)) {
+            return Ok(Color::Rgb {
+                r: (v >> 16) as u8,
+                g: (v >> 8) as u8,
+                b: v as u8,
+            });
+        }
+    }
+    Color::try_from(s).map_err(|_| format!("Can't parse {} to a color.", s))
+}
+
 pub struct Config {
     pub draw_freq: u32,
     pub cpu_freq: u32,
     pub timers_freq: u32,
+    pub step_count: u32,
+    pub breakpoints: Vec<usize>,
+    pub watchpoints: Vec<Range<usize>>,
+    pub rewind_budget: usize,
+    pub rewind_interval: u32,
+    pub conditions: Vec<Condition>,
+    // the stream stops playing the sink when it's dropped, so they're kept together
     #[cfg(feature = "sound")]
-    pub sink: Option<Sink>,
+    pub sink: Option<(OutputStream, Sink)>,
+    pub tone: Tone,
+    // wav file the sound is written to
+    pub audio_out: Option<String>,
     pub is_debug: bool,
+    pub source: String,
+    pub load_state: Option<String>,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub seed: Option<u64>,
+    pub input: String,
+    pub key_hold: u64,
+    pub keymap: Keymap,
+    pub rom_overrides: Option<RomOverrides>,
+    pub platform: String,
+    pub quirks: Quirks,
+    // lit and unlit pixels
+    pub screen_colors: (Color, Color),
+    pub image: ImageOptions,
+    // frames to save screenshots after in headless mode, and their files
+    pub screenshots: Vec<(u64, String)>,
+    // the effective configuration, printed by --print-config
+    pub profile: String,
+    pub print_config: bool,
+    pub headless: bool,
+    pub turbo: bool,
+    pub max_cycles: Option<u64>,
+    pub max_frames: Option<u64>,
     pub program: Vec<u8>,
 }
 
 impl Config {
-    pub fn load_args() -> Result<Config, String> {
-        let matches = get_matches();
+    pub fn from_matches<'a>(matches: &'a ArgMatches<'a>) -> Result<Config, String> {
+        let source = matches.value_of("source").unwrap();
 
-        #[cfg(feature = "sound")]
-        let sound_freq = matches.value_of("tone").unwrap();
+        let buf = if is_assembly(source) {
+            read_assembly(source)?
+        } else {
+            read_program(source)?
+        };
+
+        if buf.len() >= 3583 {
+            return Err(format!("Program is too big! Got {} bytes, while free memory is only 3583 bytes.", buf.len()));
+        }
+
+        // settings from the built-in overrides and the config file, the rom is found by its hash
+        let sha1 = sha1_hex(&buf);
+        let overrides = rom_overrides::lookup(&sha1);
+        let profile = Profile::load(matches, &sha1, overrides.as_ref())?;
+
+        let sound_freq = profile.value_of("tone").unwrap();
 
-        #[cfg(feature = "sound")]
         let sound_freq = match sound_freq.parse::<u32>() {
             Ok(v) => v,
             Err(_) => {
@@ -77,21 +419,41 @@ impl Config {
             }
         };
 
-        let draw_freq = matches.value_of("draw_freq").unwrap();
+        let waveform = profile.value_of("waveform").unwrap();
+
+        let waveform = match Waveform::from_name(waveform) {
+            Some(v) => v,
+            None => return Err(format!("Unknown waveform {}, expected one of: {}.", waveform, audio::WAVEFORMS.join(", "))),
+        };
+
+        let volume = profile.value_of("volume").unwrap();
+
+        let volume = match volume.parse::<u32>() {
+            Ok(v) if v <= 100 => v as f64 / 100.,
+            _ => return Err(format!("Can't parse {} to a volume from 0 to 100.", volume)),
+        };
+
+        let tone = Tone {
+            freq: sound_freq,
+            waveform,
+            volume,
+        };
+
+        let draw_freq = profile.value_of("draw_freq").unwrap();
 
         let draw_freq = match draw_freq.parse::<u32>() {
             Ok(v) => v,
             Err(_) => return Err(format!("Can't parse {} to an unsigned integer.", draw_freq)),
         };
 
-        let cpu_freq = matches.value_of("cpu_freq").unwrap();
+        let cpu_freq = profile.value_of("cpu_freq").unwrap();
 
         let cpu_freq = match cpu_freq.parse::<u32>() {
             Ok(v) => v,
             Err(_) => return Err(format!("Can't parse {} to an unsigned integer.", cpu_freq)),
         };
 
-        let timers_freq = matches.value_of("speed").unwrap();
+        let timers_freq = profile.value_of("speed").unwrap();
 
         let timers_freq = match timers_freq.parse::<u32>() {
             Ok(v) => v,
@@ -103,43 +465,191 @@ impl Config {
             }
         };
 
+        let step_count = profile.value_of("step_count").unwrap();
+
+        let step_count = match step_count.parse::<u32>() {
+            Ok(v) => v,
+            Err(_) => return Err(format!("Can't parse {} to an unsigned integer.", step_count)),
+        };
+
+        let mut breakpoints = Vec::new();
+        for addr in matches.values_of("break").into_iter().flatten() {
+            breakpoints.push(parse_address(addr)?);
+        }
+
+        let mut watchpoints = Vec::new();
+        for range in matches.values_of("watch").into_iter().flatten() {
+            watchpoints.push(parse_range(range)?);
+        }
+
+        let mut conditions = Vec::new();
+        for source in matches.values_of("break_if").into_iter().flatten() {
+            match Condition::new(source) {
+                Ok(v) => conditions.push(v),
+                Err(e) => return Err(format!("Can't parse condition `{}`: {}", source, e)),
+            }
+        }
+
+        let rewind_budget = profile.value_of("rewind_budget").unwrap();
+
+        let rewind_budget = match rewind_budget.parse::<usize>() {
+            Ok(v) => v * 1024 * 1024,
+            Err(_) => return Err(format!("Can't parse {} to an unsigned integer.", rewind_budget)),
+        };
+
+        let rewind_interval = profile.value_of("rewind_interval").unwrap();
+
+        let rewind_interval = match rewind_interval.parse::<u32>() {
+            Ok(v) if v > 0 => v,
+            _ => return Err(format!("Can't parse {} to a positive integer.", rewind_interval)),
+        };
+
         let is_debug = matches.occurrences_of("debug") > 0;
 
-        let source = matches.value_of("source").unwrap();
+        let load_state = matches.value_of("load_state").map(|v| v.to_string());
 
-        let mut f = match File::open(source) {
-            io::Result::Ok(f) => f,
-            io::Result::Err(e) => return Err(format!("{}", e)),
+        let record = matches.value_of("record").map(|v| v.to_string());
+
+        let replay = matches.value_of("replay").map(|v| v.to_string());
+
+        let audio_out = matches.value_of("audio_out").map(|v| v.to_string());
+
+        let seed = match matches.value_of("seed") {
+            Some(seed) => match seed.parse::<u64>() {
+                Ok(v) => Some(v),
+                Err(_) => return Err(format!("Can't parse {} to an unsigned integer.", seed)),
+            },
+            None => None,
+        };
+
+        let input = profile.value_of("input").unwrap().to_string();
+        if !input::BACKENDS.contains(&input.as_str()) {
+            return Err(format!("Unknown input backend {}, expected one of: {}.", input, input::BACKENDS.join(", ")));
+        }
+
+        let key_hold = profile.value_of("key_hold").unwrap();
+
+        let key_hold = match key_hold.parse::<u64>() {
+            Ok(v) => v,
+            Err(_) => return Err(format!("Can't parse {} to an unsigned integer.", key_hold)),
+        };
+
+        // a rom's own keymap goes on top of the general one
+        let mut keymap = Keymap::default();
+        if let Some(keys) = overrides.as_ref().and_then(|v| v.keys.as_ref()) {
+            keymap.apply_keys(keys)?;
+        }
+        if let Some(path) = profile.value_of("keymap") {
+            keymap.load(path)?;
+        }
+        let rom_keymap = format!("{}.keymap", source);
+        if Path::new(&rom_keymap).exists() {
+            keymap.load(&rom_keymap)?;
+        }
+        keymap.validate()?;
+
+        let platform = profile.value_of("platform").unwrap().to_string();
+
+        let mut quirks = match quirks::preset(&platform) {
+            Some(v) => v,
+            None => return Err(format!("Unknown platform {}, expected one of: {}.", platform, quirks::PLATFORMS.join(", "))),
+        };
+        if let Some(list) = profile.value_of("quirks") {
+            quirks::apply(&mut quirks, list)?;
+        }
+
+        let screen_colors = (
+            parse_color(profile.value_of("foreground").unwrap())?,
+            parse_color(profile.value_of("background").unwrap())?,
+        );
+
+        let screenshot_scale = profile.value_of("screenshot_scale").unwrap();
+
+        let screenshot_scale = match screenshot_scale.parse::<usize>() {
+            Ok(v) if v > 0 => v,
+            _ => return Err(format!("Can't parse {} to a positive integer.", screenshot_scale)),
         };
 
-        let mut buf: Vec<u8> = Vec::with_capacity(3583);
+        let image = ImageOptions::new(screen_colors, screenshot_scale);
 
-        match f.read_to_end(&mut buf) {
-            Ok(v) => {
-                if v >= 3583 {
-                    return Err(format!("Source file is too big! Got {} bytes, while free memory is only 3583 bytes.", v));
-                }
+        let mut screenshots = Vec::new();
+        let values: Vec<&str> = matches.values_of("screenshot_at_frame").into_iter().flatten().collect();
+        for pair in values.chunks(2) {
+            match pair[0].parse::<u64>() {
+                Ok(frame) => screenshots.push((frame, pair[1].to_string())),
+                Err(_) => return Err(format!("Can't parse {} to an unsigned integer.", pair[0])),
             }
-            Err(e) => return Err(format!("{}", e)),
         }
+        screenshots.sort();
+
+        let headless = matches.is_present("headless");
+
+        let turbo = matches.is_present("turbo") || timers_freq == 0;
+
+        let print_config = matches.is_present("print_config");
+
+        let max_cycles = match matches.value_of("cycles") {
+            Some(v) => match v.parse::<u64>() {
+                Ok(v) => Some(v),
+                Err(_) => return Err(format!("Can't parse {} to an unsigned integer.", v)),
+            },
+            None => None,
+        };
+
+        let max_frames = match matches.value_of("frames") {
+            Some(v) => match v.parse::<u64>() {
+                Ok(v) => Some(v),
+                Err(_) => return Err(format!("Can't parse {} to an unsigned integer.", v)),
+            },
+            None => None,
+        };
 
         #[cfg(feature = "sound")]
-        let sink = {
-            let (_stream, stream_handle) = OutputStream::try_default().unwrap();
+        // machines running headless usually have no audio device either
+        let sink = if headless || print_config {
+            None
+        } else {
+            let (stream, stream_handle) = OutputStream::try_default().unwrap();
             let sink = Sink::try_new(&stream_handle).unwrap();
-            sink.pause();
-            let source = SineWave::new(sound_freq);
-            sink.append(source);
-            Some(sink)
+            sink.append(SynthSource::new(tone, timers_freq));
+            Some((stream, sink))
         };
 
         Ok(Config {
             draw_freq,
             cpu_freq,
             timers_freq,
+            step_count,
+            breakpoints,
+            watchpoints,
+            conditions,
+            rewind_budget,
+            rewind_interval,
             #[cfg(feature = "sound")]
             sink,
+            tone,
+            audio_out,
             is_debug,
+            source: source.to_string(),
+            load_state,
+            record,
+            replay,
+            seed,
+            input,
+            key_hold,
+            keymap,
+            rom_overrides: overrides,
+            platform,
+            quirks,
+            screen_colors,
+            image,
+            screenshots,
+            profile: profile.to_ini(source),
+            print_config,
+            headless,
+            turbo,
+            max_cycles,
+            max_frames,
             program: buf,
         })
     }
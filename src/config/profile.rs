@@ -0,0 +1,161 @@
+use clap::ArgMatches;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::ini;
+use crate::rom_overrides::RomOverrides;
+
+/// Settings that can be set in the config file, by their name there and the argument they stand for.
+pub const SETTINGS: &[(&str, &str)] = &[
+    ("cpu", "cpu_freq"),
+    ("draw", "draw_freq"),
+    ("speed", "speed"),
+    ("tone", "tone"),
+    ("waveform", "waveform"),
+    ("volume", "volume"),
+    ("platform", "platform"),
+    ("quirks", "quirks"),
+    ("step_count", "step_count"),
+    ("rewind_budget", "rewind_budget"),
+    ("rewind_interval", "rewind_interval"),
+    ("input", "input"),
+    ("key_hold", "key_hold"),
+    ("keymap", "keymap"),
+    ("foreground", "foreground"),
+    ("background", "background"),
+    ("screenshot_scale", "screenshot_scale"),
+];
+
+const GLOBAL_SECTION: &str = "emulator";
+const ROM_SECTION_PREFIX: &str = "rom ";
+
+/// Path of the global config file, `$XDG_CONFIG_HOME/chiprust-emu-cli/config.ini`
+/// or `~/.config/chiprust-emu-cli/config.ini` (`%APPDATA%` on windows).
+pub fn global_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(v) => PathBuf::from(v),
+        None if cfg!(windows) => PathBuf::from(env::var_os("APPDATA")?),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("chiprust-emu-cli").join("config.ini"))
+}
+
+/// Argument values merged from the defaults, the global config file section, the built-in rom overrides,
+/// the rom's own config file section and the command line, each overriding the ones before.
+pub struct Profile<'a> {
+    matches: &'a ArgMatches<'a>,
+    // values from the config file by argument name
+    file_values: HashMap<&'static str, String>,
+    rom_sha1: String,
+}
+
+impl<'a> Profile<'a> {
+    pub fn load(matches: &'a ArgMatches<'a>, rom_sha1: &str, overrides: Option<&RomOverrides>) -> Result<Profile<'a>, String> {
+        let mut profile = Profile {
+            matches,
+            file_values: HashMap::new(),
+            rom_sha1: rom_sha1.to_string(),
+        };
+        let settings = overrides.map(RomOverrides::settings).unwrap_or_default();
+        let rom_sets_cpu = match global_path().filter(|p| p.exists()) {
+            Some(path) => profile.read_file(&path, settings)?,
+            None => {
+                profile.file_values.extend(settings);
+                false
+            }
+        };
+        // the overrides give instructions per frame, the frequency depends on the speed in effect
+        let speed = profile.value_of("speed").and_then(|v| v.parse().ok());
+        if let (Some(overrides), Some(speed), false) = (overrides, speed, rom_sets_cpu) {
+            if let Some(cpu_freq) = overrides.cpu_freq(speed) {
+                profile.file_values.insert("cpu_freq", cpu_freq.to_string());
+            }
+        }
+        Ok(profile)
+    }
+
+    // tells whether the rom's section sets the cpu frequency, which goes over the overrides' one
+    fn read_file(&mut self, path: &Path, overrides: Vec<(&'static str, String)>) -> Result<bool, String> {
+        let name = path.to_string_lossy().to_string();
+        let sections = ini::read(&name)?;
+        let rom_section = format!("{}{}", ROM_SECTION_PREFIX, self.rom_sha1);
+        let mut errors = Vec::new();
+        let mut global = Vec::new();
+        let mut rom = Vec::new();
+        for section in &sections {
+            if section.name == GLOBAL_SECTION {
+                global.push(section);
+            } else if section.name == rom_section {
+                rom.push(section);
+            } else if section.name.is_empty() {
+                if let Some(entry) = section.entries.first() {
+                    errors.push(format!(
+                        "{}:{}: settings have to be in the [{}] or a [{}<sha1>] section",
+                        name, entry.line, GLOBAL_SECTION, ROM_SECTION_PREFIX
+                    ));
+                }
+            } else if !section.name.starts_with(ROM_SECTION_PREFIX) {
+                errors.push(format!("{}:{}: unknown section [{}]", name, section.line, section.name));
+            }
+        }
+        // the rom's section goes on top of the global one, whatever the order in the file,
+        // and the built-in overrides go in between
+        self.apply(path, &global, &mut errors);
+        self.file_values.extend(overrides);
+        self.apply(path, &rom, &mut errors);
+        if errors.is_empty() {
+            Ok(rom.iter().flat_map(|s| &s.entries).any(|e| e.key == "cpu"))
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    fn apply(&mut self, path: &Path, sections: &[&ini::Section], errors: &mut Vec<String>) {
+        for section in sections {
+            for entry in &section.entries {
+                match SETTINGS.iter().find(|(key, _)| *key == entry.key) {
+                    Some((_, arg)) => {
+                        self.file_values.insert(arg, resolve(path, arg, &entry.value));
+                    }
+                    None => errors.push(format!("{}:{}: unknown setting `{}`", path.to_string_lossy(), entry.line, entry.key)),
+                }
+            }
+        }
+    }
+
+    /// Value of the argument, like `ArgMatches::value_of`. The config file
+    /// only overrides defaults, not values given on the command line.
+    pub fn value_of(&self, arg: &str) -> Option<&str> {
+        if self.matches.occurrences_of(arg) == 0 {
+            if let Some(v) = self.file_values.get(arg) {
+                return Some(v);
+            }
+        }
+        self.matches.value_of(arg)
+    }
+
+    /// The effective settings as a rom section of the config file.
+    pub fn to_ini(&self, source: &str) -> String {
+        let mut result = format!("; effective configuration for {}\n", source);
+        if let Some(path) = global_path() {
+            result.push_str(&format!("; can be put into {}\n", path.to_string_lossy()));
+        }
+        result.push_str(&format!("[{}{}]\n", ROM_SECTION_PREFIX, self.rom_sha1));
+        for (key, arg) in SETTINGS {
+            match self.value_of(arg) {
+                Some(v) => result.push_str(&format!("{} = {}\n", key, v)),
+                None => result.push_str(&format!("; {} =\n", key)),
+            }
+        }
+        result
+    }
+}
+
+// paths in the config file are relative to it
+fn resolve(path: &Path, arg: &str, value: &str) -> String {
+    match path.parent() {
+        Some(dir) if arg == "keymap" && Path::new(value).is_relative() => dir.join(value).to_string_lossy().to_string(),
+        _ => value.to_string(),
+    }
+}
@@ -0,0 +1,315 @@
+use chiprust_emu::Chip8;
+
+use crate::quirks::{Quirks, BACKEND};
+use crate::snapshot::FONT_END;
+
+const MEM_SIZE: usize = 4096;
+
+// xo-chip pitch at the start, 4000 pattern bits per second
+const DEFAULT_PITCH: u8 = 64;
+
+/// Runs instructions with the quirks asked for, the xo-chip audio instructions the backend doesn't have
+/// and `CXNN` with random numbers from a seed, the backend's can't be seeded.
+/// The backend has a fixed set of quirks and can only load memory and set the program counter, so instructions
+/// it runs differently are swapped for ones it runs right, or followed by ones setting what it left out,
+/// and the memory is put back afterwards.
+pub struct Cpu {
+    pub quirks: Quirks,
+    // xo-chip 1 bit samples set by F002
+    pub pattern: Option<[u8; 16]>,
+    // xo-chip pitch set by FX3A
+    pub pitch: u8,
+    // splitmix64 state
+    rng: u64,
+}
+
+impl Cpu {
+    pub fn new(quirks: Quirks, seed: u64) -> Cpu {
+        Cpu {
+            quirks,
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+            rng: seed,
+        }
+    }
+
+    fn random(&mut self) -> u8 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (z ^ (z >> 31)) as u8
+    }
+
+    /// Executes the instruction at the program counter, tells whether it waits for the next frame.
+    pub fn tick(&mut self, chip: &mut Chip8) -> Result<bool, &'static str> {
+        let pc = chip.get_pc();
+        // code in the font memory can't be swapped, the backend can't load there
+        if pc < FONT_END || pc + 1 >= MEM_SIZE {
+            chip.cpu_tick()?;
+            return Ok(false);
+        }
+        let opcode = chip.get_opcode(pc);
+        let x = (opcode >> 8 & 0xF) as usize;
+        if opcode & 0xF000 == 0xC000 {
+            // loads the number instead
+            let value = self.random() & opcode as u8;
+            run_instead(chip, 0x6000 | opcode & 0x0F00 | value as u16)?;
+            return Ok(false);
+        }
+        match opcode & 0xF0FF {
+            0xF002 if x == 0 => {
+                let i = chip.get_i();
+                if i + 16 > MEM_SIZE {
+                    return Err("Audio pattern out of memory");
+                }
+                let mut pattern = [0; 16];
+                for (k, v) in pattern.iter_mut().enumerate() {
+                    *v = chip.get_memory(i + k);
+                }
+                self.pattern = Some(pattern);
+                set_pc(chip, pc + 2);
+                return Ok(false);
+            }
+            0xF03A => {
+                self.pitch = chip.get_regs()[x];
+                set_pc(chip, pc + 2);
+                return Ok(false);
+            }
+            _ => {}
+        }
+        if self.quirks == BACKEND {
+            chip.cpu_tick()?;
+            return Ok(false);
+        }
+        let quirks = self.quirks;
+        match opcode & 0xF000 {
+            0x8000 => match opcode & 0xF {
+                // shifting VX is shifting VY with Y set to X
+                0x6 | 0xE if !quirks.shift_uses_vy => run_instead(chip, opcode & 0xFF0F | (x as u16) << 4)?,
+                0x1..=0x3 if quirks.logic_resets_vf => {
+                    chip.cpu_tick()?;
+                    run_at(chip, pc, 0x6F00)?;
+                }
+                _ => chip.cpu_tick()?,
+            },
+            0xB000 if quirks.jump_uses_vx => {
+                let target = (opcode & 0xFFF) as usize + chip.get_regs()[x] as usize;
+                if target < FONT_END || target + 1 >= MEM_SIZE {
+                    return Err("Jump out of the program memory");
+                }
+                set_pc(chip, target);
+            }
+            0xD000 => {
+                if quirks.wrap_sprites {
+                    chip.cpu_tick()?;
+                } else {
+                    draw_clipped(chip, opcode)?;
+                }
+                // the original interpreters only wait for the display in lores mode
+                return Ok(quirks.display_wait && !chip.display.hi_res());
+            }
+            0xF000 => match opcode & 0xFF {
+                0x55 | 0x65 if quirks.load_store_increments_i => {
+                    chip.cpu_tick()?;
+                    // ANNN only sets 12 bits
+                    let i = (chip.get_i() + x + 1) & 0xFFF;
+                    run_at(chip, pc, 0xA000 | i as u16)?;
+                }
+                _ => chip.cpu_tick()?,
+            },
+            _ => chip.cpu_tick()?,
+        }
+        Ok(false)
+    }
+}
+
+// the font memory, which `Chip8::load` writes every time
+fn font(chip: &Chip8) -> [u8; FONT_END] {
+    let mut font = [0; FONT_END];
+    for (addr, v) in font.iter_mut().enumerate() {
+        *v = chip.get_memory(addr);
+    }
+    font
+}
+
+fn set_pc(chip: &mut Chip8, pc: usize) {
+    let font = font(chip);
+    chip.load(pc, &[], Some(font));
+}
+
+// writes the bytes to memory, the program counter stays where it is
+fn write(chip: &mut Chip8, addr: usize, bytes: &[u8]) {
+    let pc = chip.get_pc();
+    let mut font = font(chip);
+    let split = FONT_END.saturating_sub(addr).min(bytes.len());
+    if split > 0 {
+        font[addr..addr + split].copy_from_slice(&bytes[..split]);
+    }
+    chip.load(addr.max(FONT_END), &bytes[split..], Some(font));
+    chip.load(pc, &[], Some(font));
+}
+
+// runs the opcode in place of the instruction at the program counter
+fn run_instead(chip: &mut Chip8, opcode: u16) -> Result<(), &'static str> {
+    let pc = chip.get_pc();
+    let original = [chip.get_memory(pc), chip.get_memory(pc + 1)];
+    write(chip, pc, &opcode.to_be_bytes());
+    let result = chip.cpu_tick();
+    write(chip, pc, &original);
+    result
+}
+
+// runs the opcode placed at the address, without moving the program counter
+fn run_at(chip: &mut Chip8, addr: usize, opcode: u16) -> Result<(), &'static str> {
+    let pc = chip.get_pc();
+    set_pc(chip, addr);
+    run_instead(chip, opcode)?;
+    set_pc(chip, pc);
+    Ok(())
+}
+
+// draws the sprite with the pixels beyond the right and the bottom edge cleared
+fn draw_clipped(chip: &mut Chip8, opcode: u16) -> Result<(), &'static str> {
+    let regs = chip.get_regs();
+    let hires = chip.display.hi_res();
+    let (width, height) = if hires { (128, 64) } else { (64, 32) };
+    let x = regs[(opcode >> 8 & 0xF) as usize] as usize % width;
+    let y = regs[(opcode >> 4 & 0xF) as usize] as usize % height;
+    // 16x16 sprites in hires mode, 8 pixels wide ones otherwise
+    let (rows, row_bytes) = match opcode & 0xF {
+        0 if hires => (16, 2),
+        n => (n as usize, 1),
+    };
+    let i = chip.get_i();
+    if i + rows * row_bytes > MEM_SIZE {
+        // out of memory, the backend panics like it always did
+        return chip.cpu_tick();
+    }
+    let original: Vec<u8> = (i..i + rows * row_bytes).map(|addr| chip.get_memory(addr)).collect();
+    let clipped: Vec<u8> = original
+        .iter()
+        .enumerate()
+        .map(|(k, &b)| {
+            let (row, column) = (k / row_bytes, x + k % row_bytes * 8);
+            if y + row >= height || column >= width {
+                0
+            } else if width - column < 8 {
+                b & !(0xFFu8 >> (width - column))
+            } else {
+                b
+            }
+        })
+        .collect();
+    if clipped == original {
+        return chip.cpu_tick();
+    }
+    write(chip, i, &clipped);
+    let result = chip.cpu_tick();
+    write(chip, i, &original);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_chip;
+    use crate::quirks;
+    use crate::snapshot::read_display;
+    use chiprust_emu::display::get_px;
+
+    fn run(platform: &str, program: &[u8]) -> (Chip8, Vec<bool>) {
+        let mut chip = new_chip(program);
+        let mut cpu = Cpu::new(quirks::preset(platform).unwrap(), 0);
+        let waits = (0..program.len() / 2).map(|_| cpu.tick(&mut chip).unwrap()).collect();
+        (chip, waits)
+    }
+
+    #[test]
+    fn shifts_vx_or_vy() {
+        let program = [0x60, 0x01, 0x61, 0x08, 0x80, 0x16];
+        assert_eq!(run("chiprust", &program).0.get_regs()[0], 4);
+        assert_eq!(run("schip-modern", &program).0.get_regs()[0], 0);
+    }
+
+    #[test]
+    fn logic_resets_vf() {
+        let program = [0x6F, 0x05, 0x80, 0x11, 0x60, 0x01];
+        assert_eq!(run("chiprust", &program).0.get_regs()[0xF], 5);
+        let (chip, _) = run("chip8", &program);
+        assert_eq!(chip.get_regs()[0xF], 0);
+        assert_eq!(chip.get_regs()[0], 1);
+        // the instructions run in its place are gone again
+        assert_eq!(chip.get_opcode(0x202), 0x8011);
+    }
+
+    #[test]
+    fn load_store_increments_i() {
+        let program = [0xA3, 0x00, 0xF2, 0x55];
+        assert_eq!(run("chiprust", &program).0.get_i(), 0x300);
+        let (chip, _) = run("chip8", &program);
+        assert_eq!(chip.get_i(), 0x303);
+        assert_eq!(chip.get_pc(), 0x204);
+    }
+
+    #[test]
+    fn jumps_with_vx() {
+        let program = [0x60, 0x10, 0x62, 0x20, 0xB2, 0x40];
+        assert_eq!(run("chiprust", &program).0.get_pc(), 0x250);
+        assert_eq!(run("schip-modern", &program).0.get_pc(), 0x260);
+    }
+
+    #[test]
+    fn clips_or_wraps_sprites() {
+        // the font's 0 at the bottom right corner
+        let program = [0x60, 0x3E, 0x61, 0x1E, 0xD0, 0x15];
+        let (mut chip, _) = run("chiprust", &program);
+        let display = read_display(&mut chip);
+        // lores pixels are 2x2, the top row of the 0 is on the last but one lores row
+        assert!(get_px(&display, 0, 60));
+        assert!(get_px(&display, 124, 60));
+        let (mut chip, _) = run("chip8", &program);
+        let display = read_display(&mut chip);
+        assert!(!get_px(&display, 0, 60));
+        assert!(get_px(&display, 124, 60));
+        assert_eq!(chip.get_memory(0), 0xF0);
+    }
+
+    #[test]
+    fn sets_the_audio_pattern_and_pitch() {
+        let mut program = vec![0xA2, 0x08, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A];
+        program.extend_from_slice(&[0xAA; 16]);
+        let mut chip = new_chip(&program);
+        let mut cpu = Cpu::new(quirks::BACKEND, 0);
+        for _ in 0..4 {
+            cpu.tick(&mut chip).unwrap();
+        }
+        assert_eq!(cpu.pattern, Some([0xAA; 16]));
+        assert_eq!(cpu.pitch, 0x70);
+        assert_eq!(chip.get_pc(), 0x208);
+    }
+
+    #[test]
+    fn random_numbers_follow_the_seed() {
+        let program = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0x0F];
+        let run = |seed| {
+            let mut chip = new_chip(&program);
+            let mut cpu = Cpu::new(quirks::BACKEND, seed);
+            for _ in 0..3 {
+                cpu.tick(&mut chip).unwrap();
+            }
+            assert_eq!(chip.get_opcode(0x200), 0xC0FF);
+            chip.get_regs()
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+        assert!(run(1)[2] <= 0x0F);
+    }
+
+    #[test]
+    fn waits_for_the_display() {
+        let program = [0x60, 0x00, 0xD0, 0x05];
+        assert_eq!(run("chiprust", &program).1, vec![false, false]);
+        assert_eq!(run("chip8", &program).1, vec![false, true]);
+    }
+}
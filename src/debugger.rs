@@ -0,0 +1,84 @@
+mod breakpoints;
+mod condition;
+mod watchpoints;
+
+pub use breakpoints::Breakpoints;
+pub use condition::Condition;
+pub use watchpoints::{parse_range, Watchpoints};
+
+use chiprust_emu::Chip8State;
+
+use crate::config::{parse_address, Config};
+
+#[derive(Clone, Default)]
+pub struct Debugger {
+    pub breakpoints: Breakpoints,
+    pub watchpoints: Watchpoints,
+    pub conditions: Vec<Condition>,
+}
+
+impl Debugger {
+    pub fn new(config: &Config) -> Debugger {
+        Debugger {
+            breakpoints: Breakpoints::new(&config.breakpoints),
+            watchpoints: Watchpoints::new(&config.watchpoints),
+            conditions: config.conditions.clone(),
+        }
+    }
+
+    /// Runs a command typed into the ui command line.
+    pub fn execute(&mut self, command: &str) -> Result<(), String> {
+        let command = command.trim();
+        let (name, args) = match command.find(' ') {
+            Some(i) => (&command[..i], command[i + 1..].trim()),
+            None => (command, ""),
+        };
+        match name {
+            "if" => {
+                let condition = Condition::new(args).map_err(|e| e.to_string())?;
+                self.conditions.push(condition);
+            }
+            "del" => {
+                let n = args
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| (1..=self.conditions.len()).contains(n))
+                    .ok_or_else(|| format!("no condition #{}", args))?;
+                self.conditions.remove(n - 1);
+            }
+            "break" => self.breakpoints.toggle(parse_address(args)?),
+            "watch" => self.watchpoints.toggle_range(parse_range(args)?),
+            "" => {}
+            _ => return Err(format!("unknown command `{}` (if, del, break, watch)", name)),
+        }
+        Ok(())
+    }
+
+    /// Whether the cpu thread has to snapshot the state around instructions.
+    pub fn is_active(&self) -> bool {
+        !self.breakpoints.is_empty() || !self.watchpoints.is_empty() || !self.conditions.is_empty()
+    }
+
+    /// Checked before executing an instruction. Returns the reason to pause, if any.
+    pub fn check_before(&mut self, state: &Chip8State) -> Option<String> {
+        if self.breakpoints.check(state.pc) {
+            return Some(format!("breakpoint at ${:04x?}", state.pc));
+        }
+        None
+    }
+
+    /// Checked after executing an instruction. Returns the reason to pause, if any.
+    pub fn check_after(&mut self, before: &Chip8State, after: &Chip8State) -> Option<String> {
+        if let Some(reason) = self.watchpoints.check(before, after) {
+            return Some(reason);
+        }
+        // every condition is evaluated so they all track their previous values
+        let mut reason = None;
+        for (n, condition) in self.conditions.iter_mut().enumerate() {
+            if condition.check(after) && reason.is_none() {
+                reason = Some(format!("condition #{} `{}` after ${:04x?}", n + 1, condition.source, before.pc));
+            }
+        }
+        reason
+    }
+}
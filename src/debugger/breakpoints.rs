@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+
+#[derive(Clone, Default)]
+pub struct Breakpoints {
+    // address -> hit count
+    points: BTreeMap<usize, u32>,
+    // address of the last hit, so resuming from a breakpoint doesn't hit it again immediately
+    ignore_once: Option<usize>,
+}
+
+impl Breakpoints {
+    pub fn new(addresses: &[usize]) -> Breakpoints {
+        let mut breakpoints = Breakpoints::default();
+        for &addr in addresses {
+            breakpoints.set(addr);
+        }
+        breakpoints
+    }
+
+    pub fn set(&mut self, addr: usize) {
+        self.points.entry(addr).or_insert(0);
+    }
+
+    pub fn clear(&mut self, addr: usize) {
+        self.points.remove(&addr);
+    }
+
+    pub fn toggle(&mut self, addr: usize) {
+        if self.contains(addr) {
+            self.clear(addr)
+        } else {
+            self.set(addr)
+        }
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        self.points.contains_key(&addr)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Iterates over (address, hit count) pairs in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, u32)> + '_ {
+        self.points.iter().map(|(&addr, &hits)| (addr, hits))
+    }
+
+    /// Checks the pc before executing an instruction, counting the hit.
+    /// Returns true if execution should pause.
+    pub fn check(&mut self, pc: usize) -> bool {
+        if self.ignore_once.take() == Some(pc) {
+            return false;
+        }
+        match self.points.get_mut(&pc) {
+            Some(hits) => {
+                *hits += 1;
+                self.ignore_once = Some(pc);
+                true
+            }
+            None => false,
+        }
+    }
+}
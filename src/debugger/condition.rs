@@ -0,0 +1,463 @@
+use chiprust_emu::Chip8State;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Var {
+    Pc,
+    I,
+    Dt,
+    St,
+    V(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+    BitNot,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinaryOp {
+    // same precedence order as in rust, higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 3,
+            BinaryOp::BitOr => 4,
+            BinaryOp::BitXor => 5,
+            BinaryOp::BitAnd => 6,
+            BinaryOp::Shl | BinaryOp::Shr => 7,
+            BinaryOp::Add | BinaryOp::Sub => 8,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 9,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Expr {
+    Num(i64),
+    Var(Var),
+    Mem(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn eval(&self, state: &Chip8State) -> i64 {
+        match self {
+            Expr::Num(n) => *n,
+            Expr::Var(Var::Pc) => state.pc as i64,
+            Expr::Var(Var::I) => state.i as i64,
+            Expr::Var(Var::Dt) => state.delay_timer as i64,
+            Expr::Var(Var::St) => state.sound_timer as i64,
+            Expr::Var(Var::V(x)) => state.regs[*x] as i64,
+            Expr::Mem(addr) => {
+                let addr = addr.eval(state);
+                if addr >= 0 && (addr as usize) < state.mem.len() {
+                    state.mem[addr as usize] as i64
+                } else {
+                    0
+                }
+            }
+            Expr::Unary(op, e) => {
+                let v = e.eval(state);
+                match op {
+                    UnaryOp::Not => (v == 0) as i64,
+                    UnaryOp::Neg => v.wrapping_neg(),
+                    UnaryOp::BitNot => !v,
+                }
+            }
+            Expr::Binary(BinaryOp::Or, a, b) => (a.eval(state) != 0 || b.eval(state) != 0) as i64,
+            Expr::Binary(BinaryOp::And, a, b) => (a.eval(state) != 0 && b.eval(state) != 0) as i64,
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(state), b.eval(state));
+                match op {
+                    BinaryOp::Eq => (a == b) as i64,
+                    BinaryOp::Ne => (a != b) as i64,
+                    BinaryOp::Lt => (a < b) as i64,
+                    BinaryOp::Le => (a <= b) as i64,
+                    BinaryOp::Gt => (a > b) as i64,
+                    BinaryOp::Ge => (a >= b) as i64,
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::Shl => a.wrapping_shl(b as u32),
+                    BinaryOp::Shr => a.wrapping_shr(b as u32),
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    // division by zero is treated as zero instead of stopping the emulator
+                    BinaryOp::Div => a.checked_div(b).unwrap_or(0),
+                    BinaryOp::Rem => a.checked_rem(b).unwrap_or(0),
+                    BinaryOp::Or | BinaryOp::And => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ParseError {
+    // character position in the source
+    pub pos: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "col {}: {}", self.pos + 1, self.message)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    // `-` is both unary and binary
+    Minus,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    End,
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        let start = pos;
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+        if c.is_ascii_alphanumeric() || c == '$' || c == '_' {
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '$' || chars[pos] == '_') {
+                pos += 1;
+            }
+            let word: String = chars[start..pos].iter().collect::<String>().to_lowercase();
+            let token = if word.starts_with(|c: char| c.is_ascii_digit() || c == '$') {
+                Token::Num(parse_number(&word).ok_or_else(|| ParseError {
+                    pos: start,
+                    message: format!("invalid number `{}`", word),
+                })?)
+            } else {
+                Token::Ident(word)
+            };
+            tokens.push((token, start));
+            continue;
+        }
+        let next = chars.get(pos + 1).copied();
+        let (token, len) = match (c, next) {
+            ('|', Some('|')) => (Token::Binary(BinaryOp::Or), 2),
+            ('&', Some('&')) => (Token::Binary(BinaryOp::And), 2),
+            ('=', Some('=')) => (Token::Binary(BinaryOp::Eq), 2),
+            ('!', Some('=')) => (Token::Binary(BinaryOp::Ne), 2),
+            ('<', Some('=')) => (Token::Binary(BinaryOp::Le), 2),
+            ('>', Some('=')) => (Token::Binary(BinaryOp::Ge), 2),
+            ('<', Some('<')) => (Token::Binary(BinaryOp::Shl), 2),
+            ('>', Some('>')) => (Token::Binary(BinaryOp::Shr), 2),
+            ('<', _) => (Token::Binary(BinaryOp::Lt), 1),
+            ('>', _) => (Token::Binary(BinaryOp::Gt), 1),
+            ('|', _) => (Token::Binary(BinaryOp::BitOr), 1),
+            ('^', _) => (Token::Binary(BinaryOp::BitXor), 1),
+            ('&', _) => (Token::Binary(BinaryOp::BitAnd), 1),
+            ('+', _) => (Token::Binary(BinaryOp::Add), 1),
+            ('*', _) => (Token::Binary(BinaryOp::Mul), 1),
+            ('/', _) => (Token::Binary(BinaryOp::Div), 1),
+            ('%', _) => (Token::Binary(BinaryOp::Rem), 1),
+            ('-', _) => (Token::Minus, 1),
+            ('!', _) => (Token::Unary(UnaryOp::Not), 1),
+            ('~', _) => (Token::Unary(UnaryOp::BitNot), 1),
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            ('[', _) => (Token::LBracket, 1),
+            (']', _) => (Token::RBracket, 1),
+            _ => {
+                return Err(ParseError {
+                    pos,
+                    message: format!("unexpected character `{}`", c),
+                })
+            }
+        };
+        tokens.push((token, start));
+        pos += len;
+    }
+    tokens.push((Token::End, chars.len()));
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Option<i64> {
+    if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = word.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        word.parse().ok()
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn next(&mut self) -> (Token, usize) {
+        let token = self.tokens[self.pos].clone();
+        if token.0 != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        Err(ParseError {
+            pos: self.tokens[self.pos].1,
+            message: message.to_string(),
+        })
+    }
+
+    fn expect(&mut self, token: Token, message: &str) -> Result<(), ParseError> {
+        if *self.peek() == token {
+            self.next();
+            Ok(())
+        } else {
+            self.error(message)
+        }
+    }
+
+    fn binary_op(&self) -> Option<BinaryOp> {
+        match self.peek() {
+            Token::Binary(op) => Some(*op),
+            Token::Minus => Some(BinaryOp::Sub),
+            _ => None,
+        }
+    }
+
+    // precedence climbing over the binary operators
+    fn expr(&mut self, min_precedence: u8) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.binary_op() {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.next();
+            let rhs = self.expr(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek().clone() {
+            Token::Unary(op) => {
+                self.next();
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            Token::Minus => {
+                self.next();
+                Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek().clone() {
+            Token::Num(n) => {
+                self.next();
+                Ok(Expr::Num(n))
+            }
+            Token::LParen => {
+                self.next();
+                let e = self.expr(0)?;
+                self.expect(Token::RParen, "expected `)`")?;
+                Ok(e)
+            }
+            Token::Ident(name) => {
+                let var = match name.as_str() {
+                    "pc" => Var::Pc,
+                    "i" => Var::I,
+                    "dt" => Var::Dt,
+                    "st" => Var::St,
+                    "mem" => {
+                        self.next();
+                        self.expect(Token::LBracket, "expected `[` after `mem`")?;
+                        let addr = self.expr(0)?;
+                        self.expect(Token::RBracket, "expected `]`")?;
+                        return Ok(Expr::Mem(Box::new(addr)));
+                    }
+                    _ => match name.strip_prefix('v').and_then(|x| usize::from_str_radix(x, 16).ok()) {
+                        Some(x) if x < 16 && name.len() == 2 => Var::V(x),
+                        _ => return self.error(&format!("unknown variable `{}`", name)),
+                    },
+                };
+                self.next();
+                Ok(Expr::Var(var))
+            }
+            Token::End => self.error("unexpected end of expression"),
+            _ => self.error("expected a number, a variable or `(`"),
+        }
+    }
+}
+
+pub fn parse(source: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+    };
+    let e = parser.expr(0)?;
+    if *parser.peek() != Token::End {
+        return parser.error("expected an operator");
+    }
+    Ok(e)
+}
+
+/// A breakpoint that triggers when its condition becomes true.
+#[derive(Clone)]
+pub struct Condition {
+    pub source: String,
+    pub hits: u32,
+    expr: Expr,
+    // only the transition from false to true pauses, so a condition like `dt == 0`
+    // doesn't stop every instruction after resuming
+    was_true: bool,
+}
+
+impl Condition {
+    pub fn new(source: &str) -> Result<Condition, ParseError> {
+        Ok(Condition {
+            source: source.trim().to_string(),
+            hits: 0,
+            expr: parse(source)?,
+            was_true: false,
+        })
+    }
+
+    /// Evaluates the condition after an instruction. Returns true if execution should pause.
+    pub fn check(&mut self, state: &Chip8State) -> bool {
+        let is_true = self.expr.eval(state) != 0;
+        let triggered = is_true && !self.was_true;
+        self.was_true = is_true;
+        if triggered {
+            self.hits += 1;
+        }
+        triggered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> Chip8State {
+        let mut mem = Box::new([0; 4096]);
+        mem[0x300] = 7;
+        let mut regs = [0; 16];
+        regs[0] = 3;
+        regs[0xf] = 1;
+        Chip8State {
+            mem,
+            regs,
+            stack: [0; 16],
+            pc: 0x200,
+            i: 0x300,
+            sp: 0,
+            sound_timer: 0,
+            delay_timer: 5,
+        }
+    }
+
+    fn eval(source: &str) -> i64 {
+        parse(source).unwrap().eval(&state())
+    }
+
+    #[test]
+    fn tokenizes_numbers_and_operators() {
+        let tokens: Vec<Token> = tokenize("$1F>=0b10<<v0").unwrap().into_iter().map(|(t, _)| t).collect();
+        assert_eq!(tokens, vec![
+            Token::Num(0x1f),
+            Token::Binary(BinaryOp::Ge),
+            Token::Num(2),
+            Token::Binary(BinaryOp::Shl),
+            Token::Ident("v0".to_string()),
+            Token::End,
+        ]);
+        assert_eq!(tokenize("pc # 1").unwrap_err().pos, 3);
+        assert_eq!(tokenize("0xzz").unwrap_err().pos, 0);
+    }
+
+    #[test]
+    fn follows_rust_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("1 << 2 + 1"), 8);
+        assert_eq!(eval("6 & 3 == 3"), 0);
+        assert_eq!(eval("1 | 2 ^ 3 & 1"), 3);
+        assert_eq!(eval("0 && 1 || 1"), 1);
+        assert_eq!(eval("10 - 4 - 3"), 3);
+        assert_eq!(eval("-v0 * 2"), -6);
+        assert_eq!(eval("!0 + 1"), 2);
+    }
+
+    #[test]
+    fn reads_the_state() {
+        assert_eq!(eval("pc == 0x200 && vF == 1"), 1);
+        assert_eq!(eval("mem[i] + dt"), 12);
+        assert_eq!(eval("mem[0x1000]"), 0);
+        assert_eq!(eval("v0 / 0"), 0);
+    }
+
+    #[test]
+    fn reports_where_parsing_failed() {
+        assert_eq!(parse("v0 ==").unwrap_err().pos, 5);
+        assert_eq!(parse("vg").unwrap_err().message, "unknown variable `vg`");
+        assert_eq!(parse("(1").unwrap_err().message, "expected `)`");
+        assert_eq!(parse("1 2").unwrap_err().message, "expected an operator");
+    }
+
+    #[test]
+    fn triggers_on_the_edge() {
+        let mut condition = Condition::new("dt == 5").unwrap();
+        let mut state = state();
+        assert!(condition.check(&state));
+        assert!(!condition.check(&state));
+        state.delay_timer = 4;
+        assert!(!condition.check(&state));
+        state.delay_timer = 5;
+        assert!(condition.check(&state));
+        assert_eq!(condition.hits, 2);
+    }
+}
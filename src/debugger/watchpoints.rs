@@ -0,0 +1,95 @@
+use chiprust_emu::{Chip8State, get_opcode};
+use std::ops::Range;
+
+use crate::config::{parse_address, parse_end_address};
+
+/// Parses a watched range written as `0x300..0x310` (end exclusive) or a single address.
+pub fn parse_range(s: &str) -> Result<Range<usize>, String> {
+    let range = match s.find("..") {
+        Some(i) => parse_address(&s[..i])?..parse_end_address(&s[i + 2..])?,
+        None => {
+            let addr = parse_address(s)?;
+            addr..addr + 1
+        }
+    };
+    if range.start >= range.end {
+        return Err(format!("Watched range {} is empty.", s));
+    }
+    Ok(range)
+}
+
+#[derive(Clone, Default)]
+pub struct Watchpoints {
+    ranges: Vec<Range<usize>>,
+}
+
+impl Watchpoints {
+    pub fn new(ranges: &[Range<usize>]) -> Watchpoints {
+        Watchpoints {
+            ranges: ranges.to_vec(),
+        }
+    }
+
+    /// Adds or removes a single byte watch.
+    pub fn toggle(&mut self, addr: usize) {
+        self.toggle_range(addr..addr + 1)
+    }
+
+    pub fn toggle_range(&mut self, range: Range<usize>) {
+        match self.ranges.iter().position(|r| *r == range) {
+            Some(i) => {
+                self.ranges.remove(i);
+            }
+            None => self.ranges.push(range),
+        }
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        self.ranges.iter().any(|r| r.contains(&addr))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Range<usize>> {
+        self.ranges.iter()
+    }
+
+    /// Compares the states around one executed instruction.
+    /// Returns the reason to pause if a watched byte changed or I moved into a watched range.
+    pub fn check(&self, before: &Chip8State, after: &Chip8State) -> Option<String> {
+        let instruction = format!("by ${:04x?} ({:04x?})", before.pc, get_opcode(&before.mem, before.pc));
+        for range in &self.ranges {
+            for addr in range.clone() {
+                if before.mem[addr] != after.mem[addr] {
+                    return Some(format!(
+                        "watch ${:04x?}..${:04x?}: ${:04x?} {:02x?} -> {:02x?} {}",
+                        range.start, range.end, addr, before.mem[addr], after.mem[addr], instruction
+                    ));
+                }
+            }
+            if before.i != after.i && range.contains(&after.i) {
+                return Some(format!(
+                    "watch ${:04x?}..${:04x?}: I = ${:04x?} {}",
+                    range.start, range.end, after.i, instruction
+                ));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_may_end_after_the_last_address() {
+        assert_eq!(parse_range("0xff0..0x1000"), Ok(0xff0..0x1000));
+        assert_eq!(parse_range("$fff"), Ok(0xfff..0x1000));
+        assert!(parse_range("0xff0..0x1001").is_err());
+        assert!(parse_range("0x1000").is_err());
+        assert!(parse_range("0x300..0x300").is_err());
+    }
+}
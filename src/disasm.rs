@@ -0,0 +1,198 @@
+mod listing;
+
+pub use listing::listing;
+
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operand {
+    V(u8),
+    Byte(u8),
+    Nibble(u8),
+    Addr(u16),
+    // 16 bit address of the xo-chip `LD I, LONG NNNN`
+    Long(u16),
+    // registers and special operands written literally, e.g. `I`, `DT`, `[I]`
+    Named(&'static str),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::V(x) => write!(f, "V{:X}", x),
+            Operand::Byte(b) => write!(f, "0x{:02X}", b),
+            Operand::Nibble(n) => write!(f, "{}", n),
+            Operand::Addr(a) => write!(f, "0x{:03X}", a),
+            Operand::Long(a) => write!(f, "LONG 0x{:04X}", a),
+            Operand::Named(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Instruction {
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    // size in bytes, 4 for the xo-chip long `LD I`
+    pub len: usize,
+}
+
+impl Instruction {
+    fn new(mnemonic: &'static str, operands: Vec<Operand>) -> Instruction {
+        Instruction {
+            mnemonic,
+            operands,
+            len: 2,
+        }
+    }
+
+    /// Addresses this instruction may continue execution at, other than the next instruction.
+    pub fn branch_target(&self) -> Option<u16> {
+        match (self.mnemonic, self.operands.as_slice()) {
+            ("JP", [Operand::Addr(a)]) | ("CALL", [Operand::Addr(a)]) => Some(*a),
+            // usually the base of a jump table
+            ("JP", [Operand::V(0), Operand::Addr(a)]) => Some(*a),
+            _ => None,
+        }
+    }
+
+    /// Address of data this instruction points I to.
+    pub fn data_target(&self) -> Option<u16> {
+        match (self.mnemonic, self.operands.as_slice()) {
+            ("LD", [Operand::Named("I"), Operand::Addr(a)])
+            | ("LD", [Operand::Named("I"), Operand::Long(a)]) => Some(*a),
+            _ => None,
+        }
+    }
+
+    /// Whether execution never falls through to the next instruction.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self.mnemonic, "JP" | "RET" | "EXIT")
+    }
+
+    /// Whether the next instruction may be skipped.
+    pub fn is_skip(&self) -> bool {
+        matches!(self.mnemonic, "SE" | "SNE" | "SKP" | "SKNP")
+    }
+
+    /// Formats the instruction, writing addresses as labels where `label` returns one.
+    pub fn format_with<F: Fn(u16) -> Option<String>>(&self, label: F) -> String {
+        let operands: Vec<String> = self
+            .operands
+            .iter()
+            .map(|op| match op {
+                Operand::Addr(a) => label(*a).unwrap_or_else(|| op.to_string()),
+                Operand::Long(a) => match label(*a) {
+                    Some(label) => format!("LONG {}", label),
+                    None => op.to_string(),
+                },
+                _ => op.to_string(),
+            })
+            .collect();
+        if operands.is_empty() {
+            self.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.mnemonic, operands.join(", "))
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format_with(|_| None))
+    }
+}
+
+// the backend's get_opcode only takes the whole 4 KB memory, listings decode any slice
+fn opcode_at(mem: &[u8], addr: usize) -> u16 {
+    (mem[addr] as u16) << 8 | mem[addr + 1] as u16
+}
+
+/// Decodes the chip-8, schip or xo-chip instruction at `addr`. Returns None for unknown opcodes.
+pub fn decode(mem: &[u8], addr: usize) -> Option<Instruction> {
+    use Operand::*;
+
+    let opcode = opcode_at(mem, addr);
+    let x = ((opcode >> 8) & 0xF) as u8;
+    let y = ((opcode >> 4) & 0xF) as u8;
+    let n = (opcode & 0xF) as u8;
+    let nn = (opcode & 0xFF) as u8;
+    let nnn = opcode & 0xFFF;
+    let i = Instruction::new;
+
+    let instruction = match opcode >> 12 {
+        0x0 => match opcode {
+            0x00E0 => i("CLS", vec![]),
+            0x00EE => i("RET", vec![]),
+            0x00FB => i("SCR", vec![]),
+            0x00FC => i("SCL", vec![]),
+            0x00FD => i("EXIT", vec![]),
+            0x00FE => i("LOW", vec![]),
+            0x00FF => i("HIGH", vec![]),
+            _ if opcode & 0xFFF0 == 0x00C0 => i("SCD", vec![Nibble(n)]),
+            _ if opcode & 0xFFF0 == 0x00D0 => i("SCU", vec![Nibble(n)]),
+            _ => i("SYS", vec![Addr(nnn)]),
+        },
+        0x1 => i("JP", vec![Addr(nnn)]),
+        0x2 => i("CALL", vec![Addr(nnn)]),
+        0x3 => i("SE", vec![V(x), Byte(nn)]),
+        0x4 => i("SNE", vec![V(x), Byte(nn)]),
+        0x5 => match n {
+            0x0 => i("SE", vec![V(x), V(y)]),
+            0x2 => i("SAVE", vec![V(x), V(y)]),
+            0x3 => i("LOAD", vec![V(x), V(y)]),
+            _ => return None,
+        },
+        0x6 => i("LD", vec![V(x), Byte(nn)]),
+        0x7 => i("ADD", vec![V(x), Byte(nn)]),
+        0x8 => {
+            let mnemonic = match n {
+                0x0 => "LD",
+                0x1 => "OR",
+                0x2 => "AND",
+                0x3 => "XOR",
+                0x4 => "ADD",
+                0x5 => "SUB",
+                0x6 => "SHR",
+                0x7 => "SUBN",
+                0xE => "SHL",
+                _ => return None,
+            };
+            i(mnemonic, vec![V(x), V(y)])
+        }
+        0x9 if n == 0 => i("SNE", vec![V(x), V(y)]),
+        0xA => i("LD", vec![Named("I"), Addr(nnn)]),
+        0xB => i("JP", vec![V(0), Addr(nnn)]),
+        0xC => i("RND", vec![V(x), Byte(nn)]),
+        0xD => i("DRW", vec![V(x), V(y), Nibble(n)]),
+        0xE => match nn {
+            0x9E => i("SKP", vec![V(x)]),
+            0xA1 => i("SKNP", vec![V(x)]),
+            _ => return None,
+        },
+        0xF => match nn {
+            0x00 if x == 0 && addr + 3 < mem.len() => Instruction {
+                len: 4,
+                ..i("LD", vec![Named("I"), Long(opcode_at(mem, addr + 2))])
+            },
+            0x01 => i("PLANE", vec![Nibble(x)]),
+            0x02 if x == 0 => i("AUDIO", vec![]),
+            0x07 => i("LD", vec![V(x), Named("DT")]),
+            0x0A => i("LD", vec![V(x), Named("K")]),
+            0x15 => i("LD", vec![Named("DT"), V(x)]),
+            0x18 => i("LD", vec![Named("ST"), V(x)]),
+            0x1E => i("ADD", vec![Named("I"), V(x)]),
+            0x29 => i("LD", vec![Named("F"), V(x)]),
+            0x30 => i("LD", vec![Named("HF"), V(x)]),
+            0x33 => i("LD", vec![Named("B"), V(x)]),
+            0x3A => i("PITCH", vec![V(x)]),
+            0x55 => i("LD", vec![Named("[I]"), V(x)]),
+            0x65 => i("LD", vec![V(x), Named("[I]")]),
+            0x75 => i("LD", vec![Named("R"), V(x)]),
+            0x85 => i("LD", vec![V(x), Named("R")]),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(instruction)
+}
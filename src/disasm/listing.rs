@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+
+use super::{decode, Instruction};
+
+const PROGRAM_START: usize = 0x200;
+const DATA_PER_LINE: usize = 8;
+
+// ordered by priority, a call target is named `sub` even if it's also jumped to
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum LabelKind {
+    Data,
+    Loc,
+    Sub,
+}
+
+fn label_name(kind: LabelKind, addr: usize) -> String {
+    let prefix = match kind {
+        LabelKind::Data => "data",
+        LabelKind::Loc => "loc",
+        LabelKind::Sub => "sub",
+    };
+    format!("{}_{:03X}", prefix, addr)
+}
+
+fn add_label(labels: &mut BTreeMap<usize, LabelKind>, addr: usize, kind: LabelKind) {
+    let entry = labels.entry(addr).or_insert(kind);
+    if kind > *entry {
+        *entry = kind;
+    }
+}
+
+fn line(text: &str, addr: usize, bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("    {:<32}; {:04X}: {}\n", text, addr, bytes.join(" "))
+}
+
+/// Disassembles a whole rom loaded at 0x200 into an assemblable listing.
+///
+/// Code is found by following jumps, calls and skips from the entry point,
+/// everything else is written as `db` data. Targets of jumps, calls and
+/// `LD I` get labels.
+pub fn listing(rom: &[u8], name: &str) -> String {
+    let end = PROGRAM_START + rom.len();
+    let mut mem = vec![0u8; end.max(4096)];
+    mem[PROGRAM_START..end].copy_from_slice(rom);
+
+    // instructions by their address and which bytes they cover
+    let mut code: Vec<Option<Instruction>> = vec![None; end];
+    let mut covered = vec![false; end];
+    let mut labels = BTreeMap::new();
+    let mut pending = vec![PROGRAM_START];
+    while let Some(mut addr) = pending.pop() {
+        while addr + 1 < end && !covered[addr] {
+            let instruction = match decode(&mem, addr) {
+                Some(v) if addr + v.len <= end && !covered[addr..addr + v.len].contains(&true) => v,
+                _ => break,
+            };
+            for byte in &mut covered[addr..addr + instruction.len] {
+                *byte = true;
+            }
+            if let Some(target) = instruction.branch_target() {
+                let kind = if instruction.mnemonic == "CALL" {
+                    LabelKind::Sub
+                } else {
+                    LabelKind::Loc
+                };
+                add_label(&mut labels, target as usize, kind);
+                pending.push(target as usize);
+            }
+            if let Some(target) = instruction.data_target() {
+                add_label(&mut labels, target as usize, LabelKind::Data);
+            }
+            if instruction.is_skip() {
+                pending.push(addr + instruction.len + 2);
+            }
+            let len = instruction.len;
+            let is_terminal = instruction.is_terminal();
+            code[addr] = Some(instruction);
+            if is_terminal {
+                break;
+            }
+            addr += len;
+        }
+    }
+
+    // only keep labels that can be placed in the listing,
+    // targets in the middle of an instruction or outside the rom stay numbers
+    let labels: BTreeMap<usize, String> = labels
+        .into_iter()
+        .filter(|&(addr, _)| addr >= PROGRAM_START && addr < end && (code[addr].is_some() || !covered[addr]))
+        .map(|(addr, kind)| (addr, label_name(kind, addr)))
+        .collect();
+
+    let mut result = format!("; {}, disassembled by chiprust-emu-cli\n", name);
+    result.push_str(&format!("    org 0x{:03X}\n", PROGRAM_START));
+    let mut addr = PROGRAM_START;
+    while addr < end {
+        if let Some(label) = labels.get(&addr) {
+            result.push_str(&format!("{}:\n", label));
+        }
+        match &code[addr] {
+            Some(instruction) => {
+                let text = instruction.format_with(|a| labels.get(&(a as usize)).cloned());
+                result.push_str(&line(&text, addr, &mem[addr..addr + instruction.len]));
+                addr += instruction.len;
+            }
+            None => {
+                let mut data_end = addr + 1;
+                while data_end < end
+                    && data_end - addr < DATA_PER_LINE
+                    && code[data_end].is_none()
+                    && !labels.contains_key(&data_end)
+                {
+                    data_end += 1;
+                }
+                let bytes: Vec<String> = mem[addr..data_end].iter().map(|b| format!("0x{:02X}", b)).collect();
+                result.push_str(&line(&format!("db {}", bytes.join(", ")), addr, &mem[addr..data_end]));
+                addr = data_end;
+            }
+        }
+    }
+    result
+}
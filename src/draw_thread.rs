@@ -1,28 +1,174 @@
-
 use chiprust_emu::Chip8;
+use crossterm::style::Color;
 use std::sync::{Arc, Mutex};
 use spin_sleep::LoopHelper;
 
-use crate::{CYCLE_RATE, DRAW_RATE, termui::TermUI};
+use crate::{CYCLE_RATE, DRAW_RATE, SPEED, termui::{self, TermUI}};
+use crate::hotkeys::{Hotkey, Hotkeys};
+use crate::run_control::{RunControl, RunState};
+use crate::debugger::Debugger;
+use crate::snapshot::Snapshot;
+use crate::rewind::Rewind;
+use crate::movie;
+use crate::screenshot::{self, ImageOptions, Screenshot};
+use crate::recording::Recording;
+use crate::scheduler::{Frame, FrameSlot};
+
+/// Everything the draw thread shares with the emulation thread.
+pub struct DrawContext {
+    // locked only by hotkeys, frames are drawn from the published ones
+    pub chip: Arc<Mutex<Chip8>>,
+    pub frames: Arc<FrameSlot>,
+    pub run_control: Arc<RunControl>,
+    pub debugger: Arc<Mutex<Debugger>>,
+    pub rewind: Arc<Mutex<Rewind>>,
+    pub hotkeys: Hotkeys,
+    pub screen_colors: (Color, Color),
+    // rom title, if the built-in overrides have it, and platform name for the label
+    pub rom_label: String,
+    // quick save file
+    pub state_path: String,
+    // screenshots are saved next to it
+    pub rom_path: String,
+    pub image: ImageOptions,
+    // snapshots undone by one rewind key press
+    pub rewind_step: usize,
+}
+
+fn quick_save(ctx: &DrawContext) -> Result<String, String> {
+    let snapshot = Snapshot::take(&mut ctx.chip.lock().unwrap());
+    snapshot.save(&ctx.state_path)?;
+    Ok(format!("state saved to {}", ctx.state_path))
+}
+
+fn quick_load(ctx: &DrawContext) -> Result<String, String> {
+    let snapshot = Snapshot::load(&ctx.state_path)?;
+    let mut chip = ctx.chip.lock().unwrap();
+    snapshot.restore(&mut chip)?;
+    // shown right away, also while paused
+    ctx.frames.publish(Frame::of(&mut chip));
+    Ok(format!("state loaded from {}", ctx.state_path))
+}
+
+fn take_screenshot(ctx: &DrawContext, frame: &Frame) -> Result<String, String> {
+    let path = screenshot::next_path(&ctx.rom_path, "png");
+    Screenshot::new(&frame.display, frame.hires).save(&path, &ctx.image)?;
+    Ok(format!("screenshot saved to {}", path))
+}
 
-pub fn draw_thread(chip: Arc<Mutex<Chip8>>, draw_freq: u32) {
+fn stop_recording(ctx: &DrawContext, recording: Recording) -> Result<String, String> {
+    let path = screenshot::next_path(&ctx.rom_path, "gif");
+    recording.save(&path, &ctx.image)?;
+    Ok(format!("{} frames recorded to {}", recording.len(), path))
+}
+
+fn rewind(ctx: &DrawContext, count: usize) -> String {
+    let mut chip = ctx.chip.lock().unwrap();
+    let mut rewind = ctx.rewind.lock().unwrap();
+    let result = rewind.rewind(&mut chip, count);
+    ctx.frames.publish(Frame::of(&mut chip));
+    match result {
+        Ok(stepped) => format!("stepped back {} snapshot(s), {} left", stepped, rewind.available()),
+        Err(e) => e,
+    }
+}
+
+fn handle_hotkey(hotkey: Hotkey, termui: &mut TermUI, frame: &Frame, ctx: &DrawContext, recording: &mut Option<Recording>) {
+    let pc = frame.state.pc;
+    let run_control = &ctx.run_control;
+    match hotkey {
+        Hotkey::TogglePause => run_control.toggle_pause(),
+        Hotkey::Turbo => run_control.toggle_turbo(),
+        Hotkey::Step => run_control.step(1),
+        Hotkey::StepMany => run_control.step(run_control.step_count),
+        Hotkey::StepFrame => run_control.step_frame(),
+        Hotkey::StepBack => {
+            if run_control.state() == RunState::Paused {
+                termui.show_message(rewind(ctx, 1))
+            }
+        }
+        Hotkey::Rewind => termui.show_message(rewind(ctx, ctx.rewind_step)),
+        Hotkey::CursorUp => termui.move_cursor(pc, -1),
+        Hotkey::CursorDown => termui.move_cursor(pc, 1),
+        Hotkey::CursorPageUp => termui.move_cursor(pc, -16),
+        Hotkey::CursorPageDown => termui.move_cursor(pc, 16),
+        Hotkey::CursorToPc => termui.reset_cursor(),
+        Hotkey::ToggleBreakpoint => ctx.debugger.lock().unwrap().breakpoints.toggle(termui.cursor(pc)),
+        Hotkey::ToggleWatchpoint => ctx.debugger.lock().unwrap().watchpoints.toggle(termui.cursor(pc)),
+        Hotkey::CommandLine => termui.open_command_line(),
+        Hotkey::QuickSave => match quick_save(ctx) {
+            Ok(message) | Err(message) => termui.show_message(message),
+        },
+        Hotkey::QuickLoad => match quick_load(ctx) {
+            Ok(message) | Err(message) => termui.show_message(message),
+        },
+        Hotkey::Screenshot => match take_screenshot(ctx, frame) {
+            Ok(message) | Err(message) => termui.show_message(message),
+        },
+        Hotkey::Record => match recording.take() {
+            Some(finished) => match stop_recording(ctx, finished) {
+                Ok(message) | Err(message) => termui.show_message(message),
+            },
+            None => {
+                *recording = Some(Recording::new(Screenshot::new(&frame.display, frame.hires)));
+                termui.show_message("recording".to_string())
+            }
+        },
+        // a recording in progress is saved on the way out
+        Hotkey::Quit => match recording.take() {
+            Some(finished) => match stop_recording(ctx, finished) {
+                Ok(message) | Err(message) => termui::exit(&message),
+            },
+            None => termui::exit(""),
+        },
+    }
+}
+
+pub fn draw_thread(ctx: DrawContext, draw_freq: u32) {
     let mut loop_helper = LoopHelper::builder()
         .report_interval_s(0.5) 
         .build_with_target_rate(draw_freq);
-    let mut termui = TermUI::new();
+    let mut termui = TermUI::new(ctx.screen_colors);
+    let mut recording: Option<Recording> = None;
+    let mut debugger_snapshot = ctx.debugger.lock().unwrap().clone();
     loop {
         loop_helper.loop_start();
         if let Some(fps) = loop_helper.report_rate() {
             unsafe {DRAW_RATE = fps}
         }
-        let (chip_state, display) = {
-            let mut chip = chip.lock().unwrap();
-            (chip.to_state(), if chip.display.dirty() {Some(*chip.display.read())} else {None})
-        };
-        termui.draw(format!("{: >5.1} cycles per second; {: >5.1} frames per second drawn", 
+        let frame = ctx.frames.take();
+        let display = if frame.dirty {Some(frame.display)} else {None};
+        if let (Some(recording), true) = (recording.as_mut(), frame.dirty) {
+            recording.push(Screenshot::new(&frame.display, frame.hires));
+        }
+        while let Some(key) = termui.poll_key() {
+            if termui.is_command_line_open() {
+                if let Some(command) = termui.edit_command_line(key) {
+                    let result = ctx.debugger.lock().unwrap().execute(&command);
+                    termui.set_command_result(result);
+                }
+            } else if let Some(hotkey) = ctx.hotkeys.get(key) {
+                handle_hotkey(hotkey, &mut termui, &frame, &ctx, &mut recording)
+            }
+        }
+        // the emulation thread holds the debugger for whole frames, the last copy does until it's free
+        if let Ok(debugger) = ctx.debugger.try_lock() {
+            debugger_snapshot = debugger.clone();
+        }
+        let mut label = format!("{}; {: >5.1} cycles per second ({:.1}x speed); {: >5.1} frames per second drawn; {}", 
+                        ctx.rom_label,
                         unsafe{CYCLE_RATE},
-                        unsafe{DRAW_RATE}
-                    ).as_str(), chip_state, display);
+                        unsafe{SPEED},
+                        unsafe{DRAW_RATE},
+                        ctx.run_control.label()
+                    );
+        if let Some(movie) = movie::label() {
+            label = format!("{}; {}", label, movie);
+        }
+        if let Some(recording) = &recording {
+            label = format!("{}; REC {} frames", label, recording.len());
+        }
+        termui.draw(&label, frame.state, display, &debugger_snapshot);
         loop_helper.loop_sleep()
     }
 }
@@ -0,0 +1,210 @@
+use chiprust_emu::Chip8;
+use std::sync::atomic::Ordering;
+
+use crate::audio::{AudioOut, Voice};
+use crate::config::Config;
+use crate::cpu::Cpu;
+use crate::debugger::Debugger;
+use crate::input;
+use crate::movie;
+use crate::quirks;
+use crate::scheduler::FrameClock;
+use crate::screenshot::{ImageOptions, Screenshot};
+
+// exit codes
+pub const EXIT_STOPPED: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+// a breakpoint, watchpoint or condition was set, but the limit came first
+pub const EXIT_TIMEOUT: i32 = 2;
+pub const EXIT_KEY_WAIT: i32 = 3;
+
+/// Why a headless run stopped.
+pub enum Stop {
+    Limit,
+    Break(String),
+    KeyWait,
+    Error(String),
+}
+
+/// Runs the emulator on the calling thread as fast as possible, without the terminal ui.
+/// Timers are ticked after every frame's worth of instructions, so runs are deterministic.
+pub struct Headless {
+    pub chip: Chip8,
+    pub cpu: Cpu,
+    debugger: Debugger,
+    clock: FrameClock,
+    // frames to save screenshots after, in order
+    pub screenshots: Vec<(u64, String)>,
+    pub image: ImageOptions,
+    // wav file the sound is written to
+    pub audio_out: Option<AudioOut>,
+    pub cycle: u64,
+    pub frame: u64,
+    // the last instruction ends the frame early
+    display_wait: bool,
+}
+
+impl Headless {
+    pub fn new(mut chip: Chip8, debugger: Debugger, cpu_freq: u32, timers_freq: u32, image: ImageOptions) -> Headless {
+        // keys go through the movie module, so a headless run can replay one
+        chip.set_handlers(&movie::key_wait_handler, &movie::key_state_handler);
+        input::set_headless();
+        Headless {
+            chip,
+            cpu: Cpu::new(quirks::BACKEND, 0),
+            debugger,
+            clock: FrameClock::new(cpu_freq, timers_freq),
+            screenshots: Vec::new(),
+            image,
+            audio_out: None,
+            cycle: 0,
+            frame: 0,
+            display_wait: false,
+        }
+    }
+
+    pub fn from_config(chip: Chip8, config: &Config, seed: u64) -> Result<Headless, String> {
+        let mut headless = Headless::new(chip, Debugger::new(config), config.cpu_freq, config.timers_freq, config.image);
+        headless.cpu = Cpu::new(config.quirks, seed);
+        headless.screenshots = config.screenshots.clone();
+        if let Some(path) = &config.audio_out {
+            headless.audio_out = Some(AudioOut::create(path, config.tone, config.timers_freq)?);
+        }
+        Ok(headless)
+    }
+
+    /// Executes one instruction, checking breakpoints, watchpoints and conditions.
+    /// Sets `display_wait` if the instruction waits for the next frame.
+    fn step(&mut self) -> Option<Stop> {
+        let before = self.chip.to_state();
+        if let Some(reason) = self.debugger.check_before(&before) {
+            return Some(Stop::Break(reason));
+        }
+        match self.cpu.tick(&mut self.chip) {
+            Ok(wait) => self.display_wait = wait,
+            Err(e) => return Some(Stop::Error(format!("{:?}", e))),
+        }
+        self.cycle += 1;
+        movie::CYCLE.fetch_add(1, Ordering::Relaxed);
+        if input::take_key_wait() {
+            return Some(Stop::KeyWait);
+        }
+        if self.debugger.is_active() {
+            if let Some(reason) = self.debugger.check_after(&before, &self.chip.to_state()) {
+                return Some(Stop::Break(reason));
+            }
+        }
+        None
+    }
+
+    /// Runs one frame, the instructions until the next timers tick.
+    pub fn run_frame(&mut self) -> Option<Stop> {
+        for _ in 0..self.clock.cycles() {
+            if let Some(stop) = self.step() {
+                return Some(stop);
+            }
+            if self.display_wait {
+                break;
+            }
+        }
+        self.clock.next_frame();
+        // the beeper plays while the sound timer is nonzero, also during the tick that takes it to zero
+        let voice = Voice::of(&self.chip, &self.cpu);
+        self.chip.timers_tick();
+        self.frame += 1;
+        if let Some(audio_out) = self.audio_out.as_mut() {
+            if let Err(e) = audio_out.tick(&voice) {
+                return Some(Stop::Error(e));
+            }
+        }
+        None
+    }
+
+    fn take_screenshots(&mut self) -> Result<(), String> {
+        while self.screenshots.first().is_some_and(|(frame, _)| *frame <= self.frame) {
+            let (_, path) = self.screenshots.remove(0);
+            Screenshot::take(&mut self.chip).save(&path, &self.image)?;
+        }
+        Ok(())
+    }
+
+    /// Runs until a limit, a breakpoint, a watchpoint, a condition or an error.
+    /// Without limits and anything to break at, a run with screenshots stops after the last one.
+    pub fn run(&mut self, max_cycles: Option<u64>, mut max_frames: Option<u64>) -> Stop {
+        if max_cycles.is_none() && max_frames.is_none() && !self.debugger.is_active() {
+            max_frames = self.screenshots.last().map(|(frame, _)| *frame);
+        }
+        loop {
+            if let Err(e) = self.take_screenshots() {
+                return Stop::Error(e);
+            }
+            if max_frames.is_some_and(|max| self.frame >= max) {
+                return Stop::Limit;
+            }
+            if max_cycles.is_some_and(|max| self.cycle + self.clock.cycles() > max) {
+                // finish the partial frame instruction by instruction
+                while self.cycle < max_cycles.unwrap() {
+                    if let Some(stop) = self.step() {
+                        return stop;
+                    }
+                }
+                return Stop::Limit;
+            }
+            if let Some(stop) = self.run_frame() {
+                return stop;
+            }
+        }
+    }
+
+    /// Exit code of the run, after printing where and why it stopped.
+    pub fn report(&mut self, stop: Stop) -> i32 {
+        let (reason, code) = match stop {
+            Stop::Limit if self.debugger.is_active() => ("limit reached".to_string(), EXIT_TIMEOUT),
+            Stop::Limit => ("limit reached".to_string(), EXIT_STOPPED),
+            Stop::Break(reason) => (reason, EXIT_STOPPED),
+            Stop::KeyWait => ("waiting for a key, there is no keyboard in headless mode".to_string(), EXIT_KEY_WAIT),
+            Stop::Error(e) => (format!("error: {}", e), EXIT_ERROR),
+        };
+        println!(
+            "stopped at cycle {}, frame {}, pc ${:04x}: {}",
+            self.cycle,
+            self.frame,
+            self.chip.to_state().pc,
+            reason
+        );
+        code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{Tone, Waveform};
+    use crossterm::style::Color;
+    use std::fs;
+
+    #[test]
+    fn sound_timer_of_one_beeps_for_a_tick() {
+        let _lock = movie::TEST_LOCK.lock().unwrap();
+        // LD V0, 1; LD ST, V0, then loops
+        let chip = crate::new_chip(&[0x60, 0x01, 0xF0, 0x18, 0x12, 0x04]);
+        let image = ImageOptions::new((Color::White, Color::Black), 1);
+        let mut headless = Headless::new(chip, Debugger::default(), 120, 60, image);
+        let path = std::env::temp_dir().join(format!("chiprust-emu-cli-{}-beep.wav", std::process::id()));
+        let path = path.to_str().unwrap();
+        let tone = Tone { freq: 441, waveform: Waveform::Square, volume: 1. };
+        headless.audio_out = Some(AudioOut::create(path, tone, 60).unwrap());
+        for _ in 0..3 {
+            assert!(headless.run_frame().is_none());
+        }
+        drop(headless);
+        let bytes = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        // 735 samples per tick, loud in the first one and fading out at the start of the second
+        let samples: Vec<i16> = bytes[44..].chunks(2).map(|v| i16::from_le_bytes([v[0], v[1]])).collect();
+        assert_eq!(samples.len(), 3 * 735);
+        assert!(samples[..735].iter().any(|v| v.abs() > 30000));
+        assert!(samples[735 + 220..].iter().all(|v| *v == 0));
+    }
+}
@@ -0,0 +1,70 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+
+use crate::input::terminal::key_name;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Hotkey {
+    TogglePause,
+    Step,
+    StepMany,
+    StepFrame,
+    StepBack,
+    Rewind,
+    CursorUp,
+    CursorDown,
+    CursorPageUp,
+    CursorPageDown,
+    CursorToPc,
+    ToggleBreakpoint,
+    ToggleWatchpoint,
+    CommandLine,
+    QuickSave,
+    QuickLoad,
+    Screenshot,
+    Record,
+    Turbo,
+    Quit,
+}
+
+/// Every hotkey with its name in keymap files and its default key.
+// default keys are chosen so they don't collide with the chip-8 keypad
+pub const HOTKEYS: &[(Hotkey, &str, &str)] = &[
+    (Hotkey::TogglePause, "pause", "P"),
+    (Hotkey::Step, "step", "N"),
+    (Hotkey::StepMany, "step_many", "M"),
+    (Hotkey::StepFrame, "step_frame", "T"),
+    (Hotkey::StepBack, "step_back", "Left"),
+    (Hotkey::Rewind, "rewind", "Backspace"),
+    (Hotkey::CursorUp, "cursor_up", "Up"),
+    (Hotkey::CursorDown, "cursor_down", "Down"),
+    (Hotkey::CursorPageUp, "cursor_page_up", "PageUp"),
+    (Hotkey::CursorPageDown, "cursor_page_down", "PageDown"),
+    (Hotkey::CursorToPc, "cursor_to_pc", "Home"),
+    (Hotkey::ToggleBreakpoint, "breakpoint", "B"),
+    (Hotkey::ToggleWatchpoint, "watchpoint", "O"),
+    (Hotkey::CommandLine, "command_line", ":"),
+    (Hotkey::QuickSave, "quick_save", "F5"),
+    (Hotkey::QuickLoad, "quick_load", "F9"),
+    (Hotkey::Screenshot, "screenshot", "F12"),
+    (Hotkey::Record, "record", "G"),
+    (Hotkey::Turbo, "turbo", "Tab"),
+    (Hotkey::Quit, "quit", "Escape"),
+];
+
+/// Hotkeys by the name of their key, see `input::terminal::key_name`.
+#[derive(Clone)]
+pub struct Hotkeys(pub HashMap<String, Hotkey>);
+
+impl Hotkeys {
+    pub fn get(&self, event: KeyEvent) -> Option<Hotkey> {
+        // ctrl-c always quits, whatever the keymap says
+        if event.modifiers.contains(KeyModifiers::CONTROL) {
+            return match event.code {
+                KeyCode::Char('c') => Some(Hotkey::Quit),
+                _ => None,
+            };
+        }
+        self.0.get(&key_name(event.code)?).copied()
+    }
+}
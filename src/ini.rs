@@ -0,0 +1,83 @@
+use std::fs;
+
+/// `key = value` line of an ini file, with its line number for error messages.
+pub struct Entry {
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+}
+
+/// Entries under a `[name]` header, entries before the first header are in a section named "".
+pub struct Section {
+    pub name: String,
+    // line of the header, zero for the section without one
+    pub line: usize,
+    pub entries: Vec<Entry>,
+}
+
+/// Parses a minimal ini format: `[section]` headers, `key = value` entries and `;` or `#` comments.
+pub fn parse(text: &str) -> Result<Vec<Section>, String> {
+    let mut sections = vec![Section {
+        name: String::new(),
+        line: 0,
+        entries: Vec::new(),
+    }];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            sections.push(Section {
+                name: name.trim().to_string(),
+                line: i + 1,
+                entries: Vec::new(),
+            });
+            continue;
+        }
+        match line.find('=') {
+            Some(pos) => sections.last_mut().unwrap().entries.push(Entry {
+                line: i + 1,
+                key: line[..pos].trim().to_string(),
+                value: line[pos + 1..].trim().to_string(),
+            }),
+            None => return Err(format!("{}: expected `key = value` or `[section]`", i + 1)),
+        }
+    }
+    Ok(sections)
+}
+
+/// Reads and parses an ini file, formatting errors with the file name.
+pub fn read(path: &str) -> Result<Vec<Section>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    parse(&text).map_err(|e| format!("{}:{}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // name, line and (line, key, value) of the entries of every section
+    type Summary = Vec<(String, usize, Vec<(usize, String, String)>)>;
+
+    #[test]
+    fn parses_sections_and_entries() {
+        let sections = parse("top = 1\n; comment\n\n[ pong ]\n# comment\nkey = a = b\n  empty =  \n").unwrap();
+        let summary: Summary = sections
+            .into_iter()
+            .map(|s| (s.name, s.line, s.entries.into_iter().map(|e| (e.line, e.key, e.value)).collect()))
+            .collect();
+        assert_eq!(summary, vec![
+            (String::new(), 0, vec![(1, "top".to_string(), "1".to_string())]),
+            ("pong".to_string(), 4, vec![
+                (6, "key".to_string(), "a = b".to_string()),
+                (7, "empty".to_string(), String::new()),
+            ]),
+        ]);
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        assert_eq!(parse("[keys]\n5 = Up\nDown").err().unwrap(), "3: expected `key = value` or `[section]`");
+    }
+}
@@ -1,49 +1,67 @@
+#[cfg(feature = "input")]
+mod device;
+pub mod terminal;
 
-use device_query::{DeviceState, keymap::Keycode};
 use bimap::BiMap;
-use std::io::{stdin, Read};
 use lazy_static::lazy_static;
-use std::str::FromStr;
+use std::sync::{atomic::{AtomicBool, Ordering}, RwLock};
 
+use crate::keymap::Keymap;
 
-lazy_static!(
-    static ref DEVICE_STATE: DeviceState = DeviceState::new();
-);
 
 lazy_static!(
-    static ref KEY_MAP: BiMap<&'static str, u8> = {
-        let mut key_map = BiMap::new();
-        key_map.insert("Key1", 1);
-        key_map.insert("Key2", 2);
-        key_map.insert("Key3", 3);
-        key_map.insert("Key4", 0xC);
-        key_map.insert("Q", 4);
-        key_map.insert("W", 5);
-        key_map.insert("E", 6);
-        key_map.insert("R", 0xD);
-        key_map.insert("A", 7);
-        key_map.insert("S", 8);
-        key_map.insert("D", 9);
-        key_map.insert("F", 0xE);
-        key_map.insert("Z", 0xA);
-        key_map.insert("X", 0);
-        key_map.insert("C", 0xB);
-        key_map.insert("V", 0xF);
-        key_map
-    };
+    // key names are the ones device_query uses, the terminal backend translates to them
+    static ref KEY_MAP: RwLock<BiMap<String, u8>> = RwLock::new(Keymap::default().key_map());
 );
 
+/// Replaces the keypad keys, see `Keymap::key_map`.
+pub fn set_key_map(key_map: BiMap<String, u8>) {
+    *KEY_MAP.write().unwrap() = key_map;
+}
+
+#[cfg(feature = "input")]
+pub const BACKENDS: &[&str] = &["device", "terminal"];
+#[cfg(not(feature = "input"))]
+pub const BACKENDS: &[&str] = &["terminal"];
+
+static USE_DEVICE: AtomicBool = AtomicBool::new(false);
+// without a keyboard nothing is pressed, and waiting for a key stops the headless run
+static HEADLESS: AtomicBool = AtomicBool::new(false);
+static KEY_WAITED: AtomicBool = AtomicBool::new(false);
+
+/// Selects the input backend by its name from `BACKENDS`.
+pub fn set_backend(name: &str) {
+    USE_DEVICE.store(name == "device", Ordering::Relaxed);
+}
+
+pub fn set_headless() {
+    HEADLESS.store(true, Ordering::Relaxed);
+}
+
+/// Whether the program waited for a key in headless mode since the last call.
+pub fn take_key_wait() -> bool {
+    KEY_WAITED.swap(false, Ordering::Relaxed)
+}
+
 pub fn key_state_handler(key: u8) -> bool {
-    let pressed = DEVICE_STATE.query_keymap();
-    return pressed.contains(&Keycode::from_str(KEY_MAP.get_by_right(&key).unwrap()).unwrap());
+    if HEADLESS.load(Ordering::Relaxed) {
+        return false;
+    }
+    #[cfg(feature = "input")]
+    if USE_DEVICE.load(Ordering::Relaxed) {
+        return device::key_state_handler(key);
+    }
+    terminal::key_state_handler(key)
 }
 
 pub fn key_wait_handler() -> u8 {
-    loop {
-        let _ = stdin().read(&mut [0u8]).unwrap();
-        let pressed = DEVICE_STATE.query_keymap();
-        if let Some(v) = KEY_MAP.get_by_left(pressed[0].to_string().as_str()) {
-            return *v
-        }
+    if HEADLESS.load(Ordering::Relaxed) {
+        KEY_WAITED.store(true, Ordering::Relaxed);
+        return 0;
+    }
+    #[cfg(feature = "input")]
+    if USE_DEVICE.load(Ordering::Relaxed) {
+        return device::key_wait_handler();
     }
+    terminal::key_wait_handler()
 }
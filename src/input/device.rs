@@ -0,0 +1,72 @@
+use device_query::{DeviceState, keymap::Keycode};
+use lazy_static::lazy_static;
+use std::{thread, time::Duration};
+
+use super::KEY_MAP;
+
+
+lazy_static!(
+    static ref DEVICE_STATE: DeviceState = DeviceState::new();
+);
+
+// device_query's FromStr doesn't know every key it reports, e.g. `Key0`, so keymap names are looked up here
+fn keycode(name: &str) -> Option<Keycode> {
+    use Keycode::*;
+
+    Some(match name {
+        "Key0" => Key0, "Key1" => Key1, "Key2" => Key2, "Key3" => Key3, "Key4" => Key4,
+        "Key5" => Key5, "Key6" => Key6, "Key7" => Key7, "Key8" => Key8, "Key9" => Key9,
+        "A" => A, "B" => B, "C" => C, "D" => D, "E" => E, "F" => F, "G" => G, "H" => H, "I" => I,
+        "J" => J, "K" => K, "L" => L, "M" => M, "N" => N, "O" => O, "P" => P, "Q" => Q, "R" => R,
+        "S" => S, "T" => T, "U" => U, "V" => V, "W" => W, "X" => X, "Y" => Y, "Z" => Z,
+        "F1" => F1, "F2" => F2, "F3" => F3, "F4" => F4, "F5" => F5, "F6" => F6,
+        "F7" => F7, "F8" => F8, "F9" => F9, "F10" => F10, "F11" => F11, "F12" => F12,
+        "Escape" => Escape, "Space" => Space, "Enter" => Enter, "Up" => Up, "Down" => Down,
+        "Left" => Left, "Right" => Right, "Backspace" => Backspace, "Tab" => Tab, "Home" => Home,
+        "End" => End, "PageUp" => PageUp, "PageDown" => PageDown, "Insert" => Insert, "Delete" => Delete,
+        "Minus" => Minus, "Equal" => Equal, "LeftBracket" => LeftBracket, "RightBracket" => RightBracket,
+        "BackSlash" => BackSlash, "Semicolon" => Semicolon, "Apostrophe" => Apostrophe, "Comma" => Comma,
+        "Dot" => Dot, "Slash" => Slash, "Grave" => Grave,
+        _ => return None,
+    })
+}
+
+pub fn key_state_handler(key: u8) -> bool {
+    let pressed = DEVICE_STATE.query_keymap();
+    match KEY_MAP.read().unwrap().get_by_right(&key).and_then(|name| keycode(name)) {
+        Some(keycode) => pressed.contains(&keycode),
+        None => false,
+    }
+}
+
+// stdin belongs to the ui's hotkey reader, so poll the keyboard instead of blocking on it
+pub fn key_wait_handler() -> u8 {
+    loop {
+        let pressed = DEVICE_STATE.query_keymap();
+        for key in pressed {
+            if let Some(v) = KEY_MAP.read().unwrap().get_by_left(&key.to_string()) {
+                return *v
+            }
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap::{DEFAULT_KEYS, NAMED_KEYS};
+
+    #[test]
+    fn knows_every_keymap_name() {
+        let digits = (0..10).map(|d| format!("Key{}", d));
+        let letters = (b'A'..=b'Z').map(|c| (c as char).to_string());
+        let functions = (1..=12).map(|n| format!("F{}", n));
+        let names = NAMED_KEYS.iter().chain(DEFAULT_KEYS.iter()).map(|v| v.to_string());
+        for name in digits.chain(letters).chain(functions).chain(names) {
+            let keycode = keycode(&name).unwrap_or_else(|| panic!("no keycode for {}", name));
+            // the wait handler looks keys up by the name device_query gives them
+            assert_eq!(keycode.to_string(), name);
+        }
+    }
+}
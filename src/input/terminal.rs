@@ -0,0 +1,152 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use lazy_static::lazy_static;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Mutex,
+};
+use std::{thread, time::{Duration, Instant}};
+
+use super::KEY_MAP;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Held {
+    Released,
+    // terminals without release events only report presses (and autorepeat),
+    // so a key counts as held for a while after each of them
+    Until(Instant),
+    Pressed,
+}
+
+struct KeyState {
+    keys: [Held; 16],
+    // counts presses, so waiting for a key only returns on a new one
+    presses: u64,
+    last_pressed: u8,
+}
+
+lazy_static!(
+    static ref KEY_STATE: Mutex<KeyState> = Mutex::new(KeyState {
+        keys: [Held::Released; 16],
+        presses: 0,
+        last_pressed: 0,
+    });
+);
+
+static HOLD_MS: AtomicU64 = AtomicU64::new(200);
+// windows always reports releases, elsewhere it needs the kitty keyboard protocol
+static RELEASE_EVENTS: AtomicBool = AtomicBool::new(cfg!(windows));
+
+/// Sets for how long a key counts as held after a press without release events.
+pub fn set_hold(ms: u64) {
+    HOLD_MS.store(ms, Ordering::Relaxed);
+}
+
+/// Tells whether the terminal reports key releases.
+pub fn set_release_events(enabled: bool) {
+    if enabled {
+        RELEASE_EVENTS.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Names a key the same way device_query does, e.g. `Key1`, `Q`, `F5` or `Semicolon`.
+/// Other printable characters, like `:`, are named by themselves.
+pub fn key_name(code: KeyCode) -> Option<String> {
+    let name = match code {
+        KeyCode::Char(c) if c.is_ascii_digit() => format!("Key{}", c),
+        KeyCode::Char(c) if c.is_ascii_alphabetic() => c.to_ascii_uppercase().to_string(),
+        KeyCode::Char(c) => match c {
+            ' ' => "Space",
+            '-' => "Minus",
+            '=' => "Equal",
+            '[' => "LeftBracket",
+            ']' => "RightBracket",
+            '\\' => "BackSlash",
+            ';' => "Semicolon",
+            '\'' => "Apostrophe",
+            ',' => "Comma",
+            '.' => "Dot",
+            '/' => "Slash",
+            '`' => "Grave",
+            c if c.is_ascii_graphic() => return Some(c.to_string()),
+            _ => return None,
+        }
+        .to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::Enter => "Enter".to_string(),
+        KeyCode::Esc => "Escape".to_string(),
+        KeyCode::Backspace => "Backspace".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::PageUp => "PageUp".to_string(),
+        KeyCode::PageDown => "PageDown".to_string(),
+        KeyCode::Home => "Home".to_string(),
+        KeyCode::End => "End".to_string(),
+        KeyCode::Insert => "Insert".to_string(),
+        KeyCode::Delete => "Delete".to_string(),
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Updates the key state from a terminal key event. Called by the ui's event reader.
+pub fn handle_event(event: KeyEvent) {
+    let key = match key_name(event.code).and_then(|name| KEY_MAP.read().unwrap().get_by_left(&name).copied()) {
+        // the keypad only has 16 keys, whatever the keymap says
+        Some(v) if v <= 0xF => v,
+        _ => return,
+    };
+    let mut state = KEY_STATE.lock().unwrap();
+    match event.kind {
+        KeyEventKind::Release => state.keys[key as usize] = Held::Released,
+        kind => {
+            state.keys[key as usize] = if RELEASE_EVENTS.load(Ordering::Relaxed) {
+                Held::Pressed
+            } else {
+                Held::Until(Instant::now() + Duration::from_millis(HOLD_MS.load(Ordering::Relaxed)))
+            };
+            if kind == KeyEventKind::Press {
+                state.presses += 1;
+                state.last_pressed = key;
+            }
+        }
+    }
+}
+
+pub fn key_state_handler(key: u8) -> bool {
+    // EX9E and EXA1 ask for VX, which can be any byte, but there are only 16 keys
+    if key > 0xF {
+        return false;
+    }
+    match KEY_STATE.lock().unwrap().keys[key as usize] {
+        Held::Released => false,
+        Held::Until(time) => Instant::now() < time,
+        Held::Pressed => true,
+    }
+}
+
+pub fn key_wait_handler() -> u8 {
+    let presses = KEY_STATE.lock().unwrap().presses;
+    loop {
+        {
+            let state = KEY_STATE.lock().unwrap();
+            if state.presses != presses {
+                return state.last_pressed;
+            }
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_past_the_keypad_are_released() {
+        assert!(!key_state_handler(0x10));
+        assert!(!key_state_handler(0xFF));
+    }
+}
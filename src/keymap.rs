@@ -0,0 +1,221 @@
+use bimap::BiMap;
+use std::collections::HashMap;
+
+use crate::hotkeys::{Hotkey, Hotkeys, HOTKEYS};
+use crate::ini;
+
+// named keys both input backends know, besides digits, letters and F1-F12
+pub const NAMED_KEYS: &[&str] = &[
+    "Escape", "Space", "Enter", "Up", "Down", "Left", "Right", "Backspace", "Tab", "Home", "End",
+    "PageUp", "PageDown", "Insert", "Delete", "Minus", "Equal", "LeftBracket", "RightBracket",
+    "BackSlash", "Semicolon", "Apostrophe", "Comma", "Dot", "Slash", "Grave",
+];
+
+// the usual layout, the left side of the keyboard mirrors the cosmac vip keypad
+pub const DEFAULT_KEYS: [&str; 16] = [
+    "X", "Key1", "Key2", "Key3", "Q", "W", "E", "A", "S", "D", "Z", "C", "Key4", "R", "F", "V",
+];
+
+/// Returns the canonical name of a key, e.g. `Key1` for `1` or `PageUp` for `pageup`.
+/// Other printable characters are only allowed for hotkeys, since only the terminal knows them.
+fn canonical_key(name: &str, is_hotkey: bool) -> Result<String, String> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_digit() {
+            return Ok(format!("Key{}", c));
+        }
+        if c.is_ascii_alphabetic() {
+            return Ok(c.to_ascii_uppercase().to_string());
+        }
+        if c.is_ascii_graphic() && is_hotkey {
+            return Ok(c.to_string());
+        }
+    }
+    let lower = name.to_lowercase();
+    if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+        if (1..=12).contains(&n) {
+            return Ok(format!("F{}", n));
+        }
+    }
+    if let Some(digit) = lower.strip_prefix("key").filter(|d| d.len() == 1 && d.chars().all(|c| c.is_ascii_digit())) {
+        return Ok(format!("Key{}", digit));
+    }
+    match NAMED_KEYS.iter().find(|k| k.to_lowercase() == lower) {
+        Some(k) => Ok(k.to_string()),
+        None => Err(format!("unknown key name `{}`", name)),
+    }
+}
+
+/// Keys of the chip-8 keypad and of the emulator hotkeys.
+#[derive(Clone)]
+pub struct Keymap {
+    // key names by the keypad key
+    keys: [String; 16],
+    // in the `HOTKEYS` order
+    hotkeys: Vec<String>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        let mut keys: [String; 16] = Default::default();
+        for (key, name) in keys.iter_mut().zip(DEFAULT_KEYS.iter()) {
+            *key = name.to_string();
+        }
+        Keymap {
+            keys,
+            hotkeys: HOTKEYS.iter().map(|(_, _, key)| key.to_string()).collect(),
+        }
+    }
+}
+
+impl Keymap {
+    /// Applies a keymap file on top of this keymap. The file has a `[keys]` section
+    /// mapping hex keypad keys to key names and a `[hotkeys]` section mapping hotkey names to key names.
+    pub fn load(&mut self, path: &str) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for section in ini::read(path)? {
+            for entry in &section.entries {
+                let result = match section.name.as_str() {
+                    "keys" => self.set_key(&entry.key, &entry.value),
+                    "hotkeys" => self.set_hotkey(&entry.key, &entry.value),
+                    "" => Err("entries have to be in the [keys] or [hotkeys] section".to_string()),
+                    name => Err(format!("unknown section [{}]", name)),
+                };
+                if let Err(e) = result {
+                    errors.push(format!("{}:{}: {}", path, entry.line, e));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    /// Applies keypad keys from a list like `5=Up,8=Down`, as the built-in rom overrides write them.
+    pub fn apply_keys(&mut self, list: &str) -> Result<(), String> {
+        for item in list.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            match item.find('=') {
+                Some(pos) => self.set_key(item[..pos].trim(), item[pos + 1..].trim())?,
+                None => return Err(format!("Can't parse key `{}`, expected key=name.", item)),
+            }
+        }
+        Ok(())
+    }
+
+    fn set_key(&mut self, key: &str, name: &str) -> Result<(), String> {
+        let key = match u8::from_str_radix(key, 16) {
+            Ok(v) if v < 16 => v,
+            _ => return Err(format!("`{}` is not a keypad key, expected a hex digit 0-F", key)),
+        };
+        self.keys[key as usize] = canonical_key(name, false)?;
+        Ok(())
+    }
+
+    fn set_hotkey(&mut self, hotkey: &str, name: &str) -> Result<(), String> {
+        match HOTKEYS.iter().position(|(_, n, _)| *n == hotkey) {
+            Some(i) => {
+                self.hotkeys[i] = canonical_key(name, true)?;
+                Ok(())
+            }
+            None => Err(format!("unknown hotkey `{}`", hotkey)),
+        }
+    }
+
+    /// Checks that no key is bound to two things at once.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut bound: HashMap<&str, String> = HashMap::new();
+        let mut errors = Vec::new();
+        let keys = self.keys.iter().enumerate().map(|(key, name)| (name, format!("keypad key {:X}", key)));
+        let hotkeys = self.hotkeys.iter().zip(HOTKEYS.iter()).map(|(name, (_, hotkey, _))| (name, hotkey.to_string()));
+        for (name, action) in keys.chain(hotkeys) {
+            match bound.get(name.as_str()) {
+                Some(other) => errors.push(format!("{} is bound to both {} and {}.", name, other, action)),
+                None => {
+                    bound.insert(name, action);
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    /// Keypad keys by key name, for the input backends.
+    pub fn key_map(&self) -> BiMap<String, u8> {
+        self.keys.iter().enumerate().map(|(key, name)| (name.clone(), key as u8)).collect()
+    }
+
+    pub fn hotkeys(&self) -> Hotkeys {
+        Hotkeys(self.hotkeys.iter().zip(HOTKEYS.iter()).map(|(name, (hotkey, _, _))| (name.clone(), *hotkey)).collect())
+    }
+
+    fn hotkey_key(&self, hotkey: Hotkey) -> &str {
+        let i = HOTKEYS.iter().position(|(h, _, _)| *h == hotkey).unwrap();
+        &self.hotkeys[i]
+    }
+
+    /// Keys for resuming and stepping, shown in the label while paused.
+    pub fn pause_hint(&self) -> String {
+        format!(
+            "{} resume, {} step, {} step many, {} step frame",
+            self.hotkey_key(Hotkey::TogglePause),
+            self.hotkey_key(Hotkey::Step),
+            self.hotkey_key(Hotkey::StepMany),
+            self.hotkey_key(Hotkey::StepFrame)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonicalizes_key_names() {
+        assert_eq!(canonical_key("1", false), Ok("Key1".to_string()));
+        assert_eq!(canonical_key("key0", false), Ok("Key0".to_string()));
+        assert_eq!(canonical_key("q", false), Ok("Q".to_string()));
+        assert_eq!(canonical_key("f12", false), Ok("F12".to_string()));
+        assert_eq!(canonical_key("pageup", false), Ok("PageUp".to_string()));
+        assert_eq!(canonical_key(":", true), Ok(":".to_string()));
+        assert!(canonical_key(":", false).is_err());
+        assert!(canonical_key("f13", false).is_err());
+        assert!(canonical_key("Key10", false).is_err());
+    }
+
+    #[test]
+    fn the_default_keymap_is_valid() {
+        assert_eq!(Keymap::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn applies_keys_and_finds_conflicts() {
+        let mut keymap = Keymap::default();
+        keymap.apply_keys("5=Up, 8 = down").unwrap();
+        assert_eq!(keymap.key_map().get_by_right(&5).map(String::as_str), Some("Up"));
+        assert_eq!(keymap.key_map().get_by_right(&8).map(String::as_str), Some("Down"));
+        assert_eq!(
+            keymap.validate(),
+            Err("Up is bound to both keypad key 5 and cursor_up.\nDown is bound to both keypad key 8 and cursor_down.".to_string())
+        );
+        assert!(keymap.apply_keys("g=Up").is_err());
+        assert!(keymap.apply_keys("5").is_err());
+    }
+
+    #[test]
+    fn loads_keymap_files() {
+        let path = std::env::temp_dir().join(format!("chiprust-emu-cli-{}.keymap", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "[keys]\n0 = Key0\n[hotkeys]\npause = Space\nnothing = X\n[other]\n").unwrap();
+        let mut keymap = Keymap::default();
+        let result = keymap.load(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(result, Err(format!("{}:5: unknown hotkey `nothing`", path)));
+        assert_eq!(keymap.key_map().get_by_right(&0).map(String::as_str), Some("Key0"));
+        assert_eq!(keymap.hotkeys().0.get("Space"), Some(&Hotkey::TogglePause));
+    }
+}
@@ -1,106 +1,170 @@
+mod asm;
+mod commands;
 mod config;
 mod termui;
-#[cfg(feature = "input")]
 mod input;
+mod ini;
+mod keymap;
+mod sha1;
+mod quirks;
+mod cpu;
+mod rom_overrides;
+mod headless;
+mod audio;
+mod screenshot;
+mod recording;
+mod regression;
+mod scheduler;
 mod draw_thread;
+mod hotkeys;
+mod debugger;
+mod disasm;
+mod snapshot;
+mod rewind;
+mod movie;
+mod run_control;
 
 use chiprust_emu::Chip8;
+use clap::ArgMatches;
 use config::Config;
-#[cfg(feature = "sound")]
-use rodio::Sink;
-use std::{thread, sync::{Arc, Mutex}};
-use spin_sleep::LoopHelper;
+use run_control::RunControl;
+use debugger::Debugger;
+use snapshot::Snapshot;
+use rewind::Rewind;
+use draw_thread::DrawContext;
+use headless::Headless;
+use audio::AudioOut;
+use cpu::Cpu;
+use scheduler::{Frame, FrameClock, FrameSlot, Scheduler};
+use std::{thread, sync::{Arc, Mutex}, time::{SystemTime, UNIX_EPOCH}};
 
 static mut CYCLE_RATE: f64 = 0.;
 static mut DRAW_RATE: f64 = 0.;
+// frames per second relative to the timers frequency
+static mut SPEED: f64 = 0.;
 
-pub fn cpu_thread(chip: Arc<Mutex<Chip8>>, cpu_freq: u32) {
-    #[cfg(feature = "input")]
-    {
-        let mut chip = chip.lock().unwrap();
-        chip.set_handlers(&input::key_wait_handler, &input::key_state_handler);
-    }
-
-    let mut loop_helper = LoopHelper::builder()
-        .report_interval_s(0.5) 
-        .build_with_target_rate(cpu_freq);
+/// Creates an emulator instance with the program loaded at 0x200.
+pub fn new_chip(program: &[u8]) -> Chip8 {
+    let mut chip = Chip8::new
+        ::<&'static (dyn Fn() -> u8 + Send + Sync + 'static),
+        &'static (dyn Fn(u8) -> bool + Send + Sync + 'static)>
+        (&|| 0, &|_| false);
 
-    loop {
-        loop_helper.loop_start();
-        {
-            let mut chip = chip.lock().unwrap();
-            chip.cpu_tick().unwrap();
-        };
-        if let Some(fps) = loop_helper.report_rate() {
-            unsafe {CYCLE_RATE = fps}
-        }
-        loop_helper.loop_sleep()
-    }
+    chip.load(0x200, program, None);
+    chip
 }
 
-fn timers_thread(chip: Arc<Mutex<Chip8>>, timers_freq: u32, #[cfg(feature = "sound")] sink: Option<Sink>) {
-    let mut loop_helper = LoopHelper::builder()
-        .report_interval_s(0.5) 
-        .build_with_target_rate(timers_freq);
-
-    #[cfg(feature = "sound")]
-    let sink = sink.unwrap();
+fn run(matches: &ArgMatches) -> Result<(), String> {
+    // load args configuration
+    let config = Config::from_matches(matches)?;
 
-    loop {
-        loop_helper.loop_start();
-        {
-            let mut chip = chip.lock().unwrap();
-            chip.timers_tick();
-            #[cfg(feature = "sound")]
-            if chip.is_sound_playing() {
-                sink.play()
-            } else {
-                sink.pause()
-            }
-        }
-        loop_helper.loop_sleep()
+    if config.print_config {
+        print!("{}", config.profile);
+        return Ok(());
     }
-}
 
-fn run() {
-    // load args configuration
-    let config = match Config::load_args() {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("{}", e);
-            return;
-        }
+    // create an emulator instance and load rom from the config
+    let mut chip = new_chip(&config.program);
+
+    input::set_backend(&config.input);
+    input::set_key_map(config.keymap.key_map());
+    input::terminal::set_hold(config.key_hold);
+
+    // a movie only replays the same way with the same rng seed
+    let replay_seed = match &config.replay {
+        Some(path) => movie::start_replay(path)?,
+        None => None,
     };
+    let seed = replay_seed.or(config.seed).unwrap_or_else(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+    });
+    if let Some(path) = &config.record {
+        movie::start_recording(path, seed)?;
+    }
 
-    // create an emulator instance and load rom from the config
-    let mut chip = Chip8::new
-        ::<&'static (dyn Fn() -> u8 + Send + Sync + 'static),
-        &'static (dyn Fn(u8) -> bool + Send + Sync + 'static)>
-        (&|| 0, &|_| false);
+    // restore a save state on top of the rom if asked to
+    if let Some(path) = &config.load_state {
+        Snapshot::load(path)?.restore(&mut chip)?;
+    }
 
-    chip.load(0x200, &config.program, None);
+    if config.headless {
+        let mut headless = Headless::from_config(chip, &config, seed)?;
+        let stop = headless.run(config.max_cycles, config.max_frames);
+        let code = headless.report(stop);
+        // finishes the wav file, exiting skips destructors
+        drop(headless);
+        std::process::exit(code);
+    }
 
+    // the renderer starts with the initial screen, the scheduler publishes every frame after it
+    let frames = Arc::new(FrameSlot::new(Frame::of(&mut chip)));
     // wrap the instance into an arc mutex
     let chip = Arc::new(Mutex::new(chip));
+    // shared pause/step state, controlled from the draw thread
+    let run_control = Arc::new(RunControl::new(config.step_count, config.keymap.pause_hint()));
+    run_control.set_turbo(config.turbo);
+    let debugger = Arc::new(Mutex::new(Debugger::new(&config)));
+    let rewind = Arc::new(Mutex::new(Rewind::new(config.rewind_budget, config.rewind_interval)));
 
-    // clone the intance and needed constant values and start the cpu thread
-    let chip_clone = chip.clone();
-    let cpu_freq = config.cpu_freq;
-    thread::spawn(move || cpu_thread(chip_clone, cpu_freq));
-    // clone the intance and needed constant values and start the timers thread
-    let chip_clone = chip.clone();
-    let timers_freq = config.timers_freq;
+    // run the emulation frame by frame on its own thread
+    let audio_out = match &config.audio_out {
+        Some(path) => Some(AudioOut::create(path, config.tone, config.timers_freq)?),
+        None => None,
+    };
+    let scheduler = Scheduler {
+        chip: chip.clone(),
+        run_control: run_control.clone(),
+        debugger: debugger.clone(),
+        rewind: rewind.clone(),
+        frames: frames.clone(),
+        audio_out,
+        cpu: Cpu::new(config.quirks, seed),
+        timers_freq: config.timers_freq,
+        clock: FrameClock::new(config.cpu_freq, config.timers_freq),
+        frame_cycle: 0,
+    };
+    thread::spawn(move || scheduler.run());
+    // the sound plays for as long as the stream and the sink are alive, until the draw thread exits
     #[cfg(feature = "sound")]
-    let sink = config.sink;
-    thread::spawn(move || timers_thread(chip_clone, timers_freq, #[cfg(feature = "sound")] sink));
+    let _sink = config.sink;
     // clone the needed constant values and start the draw thread
     let draw_freq = config.draw_freq;
-    let handle = thread::spawn(move || draw_thread::draw_thread(chip, draw_freq));
+    let ctx = DrawContext {
+        chip,
+        frames,
+        run_control,
+        debugger,
+        rewind,
+        hotkeys: config.keymap.hotkeys(),
+        screen_colors: config.screen_colors,
+        rom_label: match config.rom_overrides.as_ref().and_then(|v| v.title.as_ref()) {
+            Some(title) => format!("{}; {}", title, quirks::label(&config.platform, &config.quirks)),
+            None => quirks::label(&config.platform, &config.quirks),
+        },
+        state_path: format!("{}.state", config.source),
+        rom_path: config.source.clone(),
+        image: config.image,
+        // about a tenth of a second of gameplay per key press
+        rewind_step: (config.timers_freq.max(1) / 10 / config.rewind_interval).max(1) as usize,
+    };
+    let handle = thread::spawn(move || draw_thread::draw_thread(ctx, draw_freq));
 
     // keep running until the draw thread exits
     handle.join().unwrap();
+    Ok(())
 }
 
 fn main() {
-    run();
+    let matches = config::get_matches();
+    let result = match matches.subcommand() {
+        ("asm", Some(matches)) => commands::asm(matches),
+        ("disasm", Some(matches)) => commands::disasm(matches),
+        ("test", Some(matches)) => commands::test(matches),
+        _ if matches.is_present("info") => commands::info(&matches),
+        _ => run(&matches),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,211 @@
+use lazy_static::lazy_static;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{LineWriter, Write};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+const HEADER: &str = "c8m 1";
+
+/// Number of instructions executed so far, counted by the cpu thread.
+pub static CYCLE: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Event {
+    // answer to "is this key pressed"
+    State { cycle: u64, key: u8, pressed: bool },
+    // answer to "wait for a key press"
+    Wait { cycle: u64, key: u8 },
+}
+
+impl Event {
+    fn to_line(self) -> String {
+        match self {
+            Event::State { cycle, key, pressed } => format!("s {} {:x} {}\n", cycle, key, pressed as u8),
+            Event::Wait { cycle, key } => format!("w {} {:x}\n", cycle, key),
+        }
+    }
+
+    fn from_line(line: &str) -> Option<Event> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["s", cycle, key, pressed] => Some(Event::State {
+                cycle: cycle.parse().ok()?,
+                key: u8::from_str_radix(key, 16).ok()?,
+                pressed: *pressed == "1",
+            }),
+            ["w", cycle, key] => Some(Event::Wait {
+                cycle: cycle.parse().ok()?,
+                key: u8::from_str_radix(key, 16).ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+enum Mode {
+    Off,
+    Recording(LineWriter<File>),
+    Replaying {
+        events: VecDeque<Event>,
+        total: usize,
+        desync: Option<u64>,
+    },
+}
+
+lazy_static!(
+    static ref MODE: Mutex<Mode> = Mutex::new(Mode::Off);
+);
+
+// held by tests that record, replay or count cycles, they share the state above
+#[cfg(test)]
+pub static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Stops recording or replaying and starts counting cycles from zero, for running another rom.
+pub fn reset() {
+    *MODE.lock().unwrap() = Mode::Off;
+    CYCLE.store(0, Ordering::Relaxed);
+}
+
+/// Cycle at which the replay went out of sync, if it did.
+pub fn desync() -> Option<u64> {
+    match &*MODE.lock().unwrap() {
+        Mode::Replaying { desync, .. } => *desync,
+        _ => None,
+    }
+}
+
+/// Starts writing every key query and its answer to the file, after the rng seed.
+pub fn start_recording(path: &str, seed: u64) -> Result<(), String> {
+    let mut file = LineWriter::new(File::create(path).map_err(|e| format!("Can't create {}: {}", path, e))?);
+    writeln!(file, "{} seed={}", HEADER, seed).map_err(|e| format!("Can't write {}: {}", path, e))?;
+    *MODE.lock().unwrap() = Mode::Recording(file);
+    Ok(())
+}
+
+/// Starts answering key queries from the file. Returns the rng seed the movie was recorded with,
+/// movies recorded while the seed couldn't be set don't have one.
+pub fn start_replay(path: &str) -> Result<Option<u64>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    let mut lines = text.lines();
+    let seed = match lines.next().and_then(|header| header.strip_prefix(HEADER)) {
+        Some(rest) => rest.trim().strip_prefix("seed=").and_then(|seed| seed.parse::<u64>().ok()),
+        None => return Err(format!("{} is not a movie file.", path)),
+    };
+    let mut events = VecDeque::new();
+    for (i, line) in lines.enumerate() {
+        match Event::from_line(line) {
+            Some(event) => events.push_back(event),
+            None => return Err(format!("{}:{}: invalid movie event `{}`", path, i + 2, line)),
+        }
+    }
+    *MODE.lock().unwrap() = Mode::Replaying {
+        total: events.len(),
+        events,
+        desync: None,
+    };
+    Ok(seed)
+}
+
+// returns the recorded answer, None if the replay ended or went out of sync
+fn replay(expected: Event) -> Option<Event> {
+    let mut mode = MODE.lock().unwrap();
+    if let Mode::Replaying { events, desync, .. } = &mut *mode {
+        if desync.is_some() {
+            return None;
+        }
+        let event = events.pop_front()?;
+        let matches = match (event, expected) {
+            (Event::State { cycle, key, .. }, Event::State { cycle: c, key: k, .. }) => cycle == c && key == k,
+            (Event::Wait { cycle, .. }, Event::Wait { cycle: c, .. }) => cycle == c,
+            _ => false,
+        };
+        if matches {
+            return Some(event);
+        }
+        *desync = Some(CYCLE.load(Ordering::Relaxed));
+    }
+    None
+}
+
+fn record(event: Event) {
+    if let Mode::Recording(file) = &mut *MODE.lock().unwrap() {
+        // losing a movie isn't worth stopping the emulator for
+        let _ = file.write_all(event.to_line().as_bytes());
+    }
+}
+
+pub fn key_state_handler(key: u8) -> bool {
+    let cycle = CYCLE.load(Ordering::Relaxed);
+    let expected = Event::State { cycle, key, pressed: false };
+    if let Some(Event::State { pressed, .. }) = replay(expected) {
+        return pressed;
+    }
+    let pressed = crate::input::key_state_handler(key);
+    record(Event::State { cycle, key, pressed });
+    pressed
+}
+
+pub fn key_wait_handler() -> u8 {
+    let cycle = CYCLE.load(Ordering::Relaxed);
+    if let Some(Event::Wait { key, .. }) = replay(Event::Wait { cycle, key: 0 }) {
+        return key;
+    }
+    let key = crate::input::key_wait_handler();
+    record(Event::Wait { cycle, key });
+    key
+}
+
+/// Recording or replay progress for the status label.
+pub fn label() -> Option<String> {
+    match &*MODE.lock().unwrap() {
+        Mode::Off => None,
+        Mode::Recording(_) => Some("REC".to_string()),
+        Mode::Replaying { desync: Some(cycle), .. } => Some(format!("REPLAY out of sync at cycle {}", cycle)),
+        Mode::Replaying { events, total, .. } if events.is_empty() => Some(format!("REPLAY ended ({} events)", total)),
+        Mode::Replaying { events, total, .. } => Some(format!("REPLAY {}/{}", total - events.len(), total)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_survive_their_lines() {
+        let events = [
+            Event::State { cycle: 12, key: 0xa, pressed: true },
+            Event::State { cycle: 0, key: 0, pressed: false },
+            Event::Wait { cycle: 1 << 40, key: 0xf },
+        ];
+        for event in &events {
+            assert_eq!(Event::from_line(&event.to_line()), Some(*event));
+        }
+        assert_eq!(Event::from_line("s 1 g 1"), None);
+        assert_eq!(Event::from_line("w 1"), None);
+    }
+
+    #[test]
+    fn replays_until_out_of_sync() {
+        let _lock = TEST_LOCK.lock().unwrap();
+        let path = std::env::temp_dir().join(format!("chiprust-emu-cli-{}.c8m", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "c8m 1\n").unwrap();
+        assert_eq!(start_replay(path), Ok(None));
+        fs::write(path, "c8m 1 seed=42\ns 3 a 1\nw 5 7\ns 9 1 0\n").unwrap();
+        assert_eq!(start_replay(path), Ok(Some(42)));
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(replay(Event::State { cycle: 3, key: 0xa, pressed: false }), Some(Event::State { cycle: 3, key: 0xa, pressed: true }));
+        assert_eq!(replay(Event::Wait { cycle: 5, key: 0 }), Some(Event::Wait { cycle: 5, key: 7 }));
+        assert_eq!(label(), Some("REPLAY 2/3".to_string()));
+        CYCLE.store(8, Ordering::Relaxed);
+        assert_eq!(replay(Event::State { cycle: 8, key: 1, pressed: false }), None);
+        assert_eq!(desync(), Some(8));
+        assert_eq!(label(), Some("REPLAY out of sync at cycle 8".to_string()));
+        reset();
+        assert_eq!(label(), None);
+    }
+}
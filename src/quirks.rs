@@ -0,0 +1,121 @@
+/// Behaviors chip-8 interpreters disagree on. The backend has a fixed set of them,
+/// `cpu::Cpu` changes the instructions affected by the others.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Quirks {
+    // 8XY6 and 8XYE shift VY into VX, instead of shifting VX
+    pub shift_uses_vy: bool,
+    // FX55 and FX65 leave I after the last register
+    pub load_store_increments_i: bool,
+    // BXNN jumps to XNN plus VX, instead of NNN plus V0
+    pub jump_uses_vx: bool,
+    // 8XY1, 8XY2 and 8XY3 set VF to zero
+    pub logic_resets_vf: bool,
+    // sprites are drawn across the screen edges to the other side, instead of being clipped
+    pub wrap_sprites: bool,
+    // drawing in lores mode ends the frame
+    pub display_wait: bool,
+}
+
+/// The quirks of the backend itself.
+pub const BACKEND: Quirks = Quirks {
+    shift_uses_vy: true,
+    load_store_increments_i: false,
+    jump_uses_vx: false,
+    logic_resets_vf: false,
+    wrap_sprites: true,
+    display_wait: false,
+};
+
+/// Platforms with quirk presets, the first one is the default.
+pub const PLATFORMS: &[&str] = &["chiprust", "chip8", "schip-legacy", "schip-modern", "xo-chip"];
+
+/// Quirks of the original interpreter of the platform.
+pub fn preset(platform: &str) -> Option<Quirks> {
+    let quirks = match platform {
+        // the default, roms run like they always did
+        "chiprust" => BACKEND,
+        // cosmac vip
+        "chip8" => Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            wrap_sprites: false,
+            display_wait: true,
+        },
+        // schip 1.1 on the hp48, which waits for the display in lores mode
+        "schip-legacy" => Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            display_wait: true,
+        },
+        // schip as most modern interpreters and games expect it
+        "schip-modern" => Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            display_wait: false,
+        },
+        // octo
+        "xo-chip" => Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: true,
+            display_wait: false,
+        },
+        _ => return None,
+    };
+    Some(quirks)
+}
+
+/// Quirk names for `--quirks`.
+pub const QUIRK_NAMES: &[&str] = &["shift_vy", "load_store_i", "jump_vx", "vf_reset", "wrap", "display_wait"];
+
+fn quirk_mut<'a>(quirks: &'a mut Quirks, name: &str) -> Option<&'a mut bool> {
+    let quirk = match name {
+        "shift_vy" => &mut quirks.shift_uses_vy,
+        "load_store_i" => &mut quirks.load_store_increments_i,
+        "jump_vx" => &mut quirks.jump_uses_vx,
+        "vf_reset" => &mut quirks.logic_resets_vf,
+        "wrap" => &mut quirks.wrap_sprites,
+        "display_wait" => &mut quirks.display_wait,
+        _ => return None,
+    };
+    Some(quirk)
+}
+
+/// Changes quirks from a list like `shift_vy=off,wrap=on`.
+pub fn apply(quirks: &mut Quirks, list: &str) -> Result<(), String> {
+    for item in list.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let (name, value) = match item.find('=') {
+            Some(pos) => (item[..pos].trim(), item[pos + 1..].trim()),
+            None => return Err(format!("Can't parse quirk `{}`, expected name=on or name=off.", item)),
+        };
+        let quirk = match quirk_mut(quirks, name) {
+            Some(v) => v,
+            None => return Err(format!("Unknown quirk {}, expected one of: {}.", name, QUIRK_NAMES.join(", "))),
+        };
+        *quirk = match value {
+            "on" | "true" | "1" => true,
+            "off" | "false" | "0" => false,
+            _ => return Err(format!("Can't parse quirk `{}`, expected name=on or name=off.", item)),
+        };
+    }
+    Ok(())
+}
+
+/// Name of the platform for the label, marked with `*` if some quirks differ from its preset.
+pub fn label(platform: &str, quirks: &Quirks) -> String {
+    if preset(platform).as_ref() == Some(quirks) {
+        platform.to_string()
+    } else {
+        format!("{}*", platform)
+    }
+}
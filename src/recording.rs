@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use crate::screenshot::{ImageOptions, Screenshot};
+
+// most viewers show frames shorter than 2/100 s for a tenth of a second instead
+const MIN_DELAY: Duration = Duration::from_millis(20);
+// lzw codes are at most 12 bits long
+const MAX_CODES: u16 = 4096;
+
+/// Screens captured while recording, with the time they were drawn at.
+pub struct Recording {
+    start: Instant,
+    frames: Vec<(Duration, Screenshot)>,
+}
+
+impl Recording {
+    /// Starts recording with the current screen as the first frame.
+    pub fn new(screen: Screenshot) -> Recording {
+        Recording {
+            start: Instant::now(),
+            frames: vec![(Duration::from_secs(0), screen)],
+        }
+    }
+
+    /// Adds a screen drawn now. It replaces the last one if that one wouldn't be shown long enough.
+    pub fn push(&mut self, screen: Screenshot) {
+        let at = self.start.elapsed();
+        match self.frames.last_mut() {
+            Some((last_at, last)) if at - *last_at < MIN_DELAY => *last = screen,
+            _ => self.frames.push((at, screen)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Looping gif of the frames in the options' colors and scale, each shown until the next one was drawn
+    /// and the last one until now. Lores frames are scaled up if the resolution changed while recording.
+    pub fn to_gif(&self, options: &ImageOptions) -> Vec<u8> {
+        let end = self.start.elapsed();
+        let scale = options.scale;
+        let width = self.frames.iter().map(|(_, f)| f.width).max().unwrap_or(64);
+        let height = self.frames.iter().map(|(_, f)| f.height).max().unwrap_or(32);
+        let lit = |frame: &Screenshot, x: usize, y: usize| frame.get(x * frame.width / width, y * frame.height / height);
+
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&((width * scale) as u16).to_le_bytes());
+        gif.extend_from_slice(&((height * scale) as u16).to_le_bytes());
+        // a global color table of 2 colors, background color 0, square pixels
+        gif.extend_from_slice(&[0xf0, 0, 0]);
+        gif.extend_from_slice(&options.palette.concat());
+        // loop forever
+        gif.extend_from_slice(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+
+        for (n, (at, frame)) in self.frames.iter().enumerate() {
+            let next_at = self.frames.get(n + 1).map(|(at, _)| *at).unwrap_or(end);
+            let delay = centis(next_at).saturating_sub(centis(*at)).max(2).min(u16::MAX as u64) as u16;
+            // frames are drawn over the previous ones, so only the part that changed is stored
+            let (left, top, right, bottom) = match n {
+                0 => (0, 0, width, height),
+                _ => {
+                    let previous = &self.frames[n - 1].1;
+                    let changed = (0..height)
+                        .flat_map(|y| (0..width).map(move |x| (x, y)))
+                        .filter(|&(x, y)| lit(frame, x, y) != lit(previous, x, y));
+                    changed.fold((width, height, 0, 0), |(l, t, r, b), (x, y)| (l.min(x), t.min(y), r.max(x + 1), b.max(y + 1)))
+                }
+            };
+            // an unchanged frame still needs an image, a single pixel of it
+            let (left, top, right, bottom) = if left < right { (left, top, right, bottom) } else { (0, 0, 1, 1) };
+
+            // graphic control extension: leave the frame in place, the delay, no transparency
+            gif.extend_from_slice(&[0x21, 0xf9, 4, 0x04]);
+            gif.extend_from_slice(&delay.to_le_bytes());
+            gif.extend_from_slice(&[0, 0]);
+            // image descriptor without a local color table
+            gif.push(0x2c);
+            for value in &[left, top, right - left, bottom - top] {
+                gif.extend_from_slice(&((value * scale) as u16).to_le_bytes());
+            }
+            gif.push(0);
+
+            let mut indices = Vec::with_capacity((right - left) * (bottom - top) * scale * scale);
+            for y in top * scale..bottom * scale {
+                for x in left * scale..right * scale {
+                    indices.push(lit(frame, x / scale, y / scale) as u8);
+                }
+            }
+            // the smallest code size gifs allow, even for 2 colors
+            gif.push(2);
+            for block in lzw(2, &indices).chunks(255) {
+                gif.push(block.len() as u8);
+                gif.extend_from_slice(block);
+            }
+            gif.push(0);
+        }
+        gif.push(0x3b);
+        gif
+    }
+
+    pub fn save(&self, path: &str, options: &ImageOptions) -> Result<(), String> {
+        fs::write(path, self.to_gif(options)).map_err(|e| format!("Can't write {}: {}", path, e))
+    }
+}
+
+// gif delays are in hundredths of a second, rounding every timestamp keeps the delays from drifting
+fn centis(time: Duration) -> u64 {
+    (time.as_millis() as u64 + 5) / 10
+}
+
+// variable length lzw as gifs use it, codes are packed starting from the lowest bit
+fn lzw(min_code_size: u8, indices: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut bytes = Vec::new();
+    let (mut bits, mut bit_count) = (0u32, 0u32);
+    let mut write = |code: u16, size: u32| {
+        bits |= (code as u32) << bit_count;
+        bit_count += size;
+        while bit_count >= 8 {
+            bytes.push(bits as u8);
+            bits >>= 8;
+            bit_count -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = min_code_size as u32 + 1;
+    write(clear, size);
+    let mut prefix: Option<u16> = None;
+    for &index in indices {
+        let current = match prefix {
+            None => {
+                prefix = Some(index as u16);
+                continue;
+            }
+            Some(current) => current,
+        };
+        if let Some(&code) = table.get(&(current, index)) {
+            prefix = Some(code);
+            continue;
+        }
+        write(current, size);
+        if next == MAX_CODES {
+            // the table is full, start over
+            write(clear, size);
+            table.clear();
+            next = end + 1;
+            size = min_code_size as u32 + 1;
+        } else {
+            if next == 1 << size {
+                size += 1;
+            }
+            table.insert((current, index), next);
+            next += 1;
+        }
+        prefix = Some(index as u16);
+    }
+    if let Some(current) = prefix {
+        write(current, size);
+        // the decoder adds the last entry after reading this code too
+        if next == 1 << size && size < 12 {
+            size += 1;
+        }
+    }
+    write(end, size);
+    if bit_count > 0 {
+        bytes.push(bits as u8);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a plain gif lzw decoder, as viewers implement it
+    fn unlzw(min_code_size: u8, bytes: &[u8]) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let reset = || (0..clear + 2).map(|v| vec![v as u8]).collect::<Vec<Vec<u8>>>();
+        let mut table = reset();
+        let mut size = min_code_size as usize + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut result = Vec::new();
+        let mut bit = 0;
+        loop {
+            let code = (0..size).fold(0, |code, n| code | ((bytes[(bit + n) / 8] as usize >> ((bit + n) % 8)) & 1) << n);
+            bit += size;
+            if code == clear {
+                table = reset();
+                size = min_code_size as usize + 1;
+                previous = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return result;
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => [&previous[..], &previous[..1]].concat(),
+                (None, None) => panic!("code {} before any other", code),
+            };
+            result.extend_from_slice(&entry);
+            if let Some(previous) = previous {
+                if table.len() < MAX_CODES as usize {
+                    table.push([&previous[..], &entry[..1]].concat());
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            previous = Some(entry);
+        }
+    }
+
+    // a lores screen
+    fn screen(lit: impl Fn(usize, usize) -> bool) -> Screenshot {
+        let mut display = [0u128; 64];
+        for y in 0..32 {
+            for x in (0..64).filter(|&x| lit(x, y)) {
+                display[y * 2] |= 1 << (127 - x * 2);
+            }
+        }
+        Screenshot::new(&display, false)
+    }
+
+    #[test]
+    fn lzw_decodes_to_the_indices() {
+        let cases: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![1],
+            vec![0; 1000],
+            (0..1000).map(|n| (n % 3 == 0) as u8).collect(),
+            // enough different strings to fill the table and start over, twice
+            (0..40000u32).map(|n| (n.wrapping_mul(2_654_435_761) >> 13) as u8 & 1).collect(),
+        ];
+        for indices in cases {
+            assert_eq!(unlzw(2, &lzw(2, &indices)), indices, "{} indices", indices.len());
+        }
+    }
+
+    #[test]
+    fn short_frames_are_replaced() {
+        let mut recording = Recording::new(screen(|_, _| false));
+        recording.push(screen(|x, _| x == 0));
+        assert_eq!(recording.len(), 1);
+        assert!(recording.frames[0].1.get(0, 5));
+        std::thread::sleep(MIN_DELAY);
+        recording.push(screen(|_, _| false));
+        assert_eq!(recording.len(), 2);
+    }
+
+    #[test]
+    fn frames_store_only_what_changed() {
+        let mut recording = Recording::new(screen(|_, _| false));
+        recording.frames.push((Duration::from_millis(500), screen(|x, y| (10..13).contains(&x) && y == 4)));
+        recording.frames.push((Duration::from_millis(510), screen(|x, y| (10..13).contains(&x) && y == 4)));
+        let options = ImageOptions { palette: [[0; 3], [255; 3]], scale: 2 };
+        let gif = recording.to_gif(&options);
+
+        assert_eq!(&gif[..13], b"GIF89a\x80\x00\x40\x00\xf0\x00\x00");
+        assert_eq!(gif.last(), Some(&0x3b));
+        // graphic control extensions with the delays, then image descriptors with the rectangles
+        let find = |pattern: &[u8]| (0..gif.len()).filter(|&n| gif[n..].starts_with(pattern)).collect::<Vec<usize>>();
+        let delays: Vec<u16> = find(&[0x21, 0xf9, 4]).iter().map(|&n| u16::from_le_bytes([gif[n + 4], gif[n + 5]])).collect();
+        assert_eq!(delays[..2], [50, 2]);
+        let rectangles: Vec<Vec<u8>> = find(&[0, 0, 0x2c]).iter().map(|&n| gif[n + 3..n + 11].to_vec()).collect();
+        assert_eq!(rectangles, vec![
+            vec![0, 0, 0, 0, 128, 0, 64, 0],
+            vec![20, 0, 8, 0, 6, 0, 2, 0],
+            vec![0, 0, 0, 0, 2, 0, 2, 0],
+        ]);
+    }
+}
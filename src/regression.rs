@@ -0,0 +1,207 @@
+use crossterm::style::Color;
+use std::path::Path;
+
+use crate::config::{is_assembly, read_assembly, read_program};
+use crate::cpu::Cpu;
+use crate::debugger::Debugger;
+use crate::headless::{Headless, Stop};
+use crate::ini;
+use crate::movie;
+use crate::new_chip;
+use crate::quirks;
+use crate::screenshot::{ImageOptions, Screenshot};
+use crate::sha1::sha1_hex;
+
+// the emulator's own defaults, so cases don't depend on the config file or the built-in rom overrides
+const DEFAULT_CPU_FREQ: u32 = 60;
+const DEFAULT_TIMERS_FREQ: u32 = 60;
+const DIFF_SCALE: usize = 4;
+
+/// A test case, a section of the manifest.
+struct Case {
+    rom: String,
+    platform: String,
+    quirks: Option<String>,
+    cpu_freq: u32,
+    timers_freq: u32,
+    seed: u64,
+    movie: Option<String>,
+    frames: Option<u64>,
+    cycles: Option<u64>,
+    // expected screenshot file and SHA-1 of the screen's text dump
+    expect: Option<String>,
+    hash: Option<String>,
+}
+
+impl Case {
+    fn parse(section: &ini::Section, dir: &Path) -> Result<Case, String> {
+        let mut case = Case {
+            rom: String::new(),
+            platform: quirks::PLATFORMS[0].to_string(),
+            quirks: None,
+            cpu_freq: DEFAULT_CPU_FREQ,
+            timers_freq: DEFAULT_TIMERS_FREQ,
+            seed: 0,
+            movie: None,
+            frames: None,
+            cycles: None,
+            expect: None,
+            hash: None,
+        };
+        // paths are relative to the manifest
+        let path = |v: &str| dir.join(v).to_string_lossy().to_string();
+        for entry in &section.entries {
+            let number = || entry.value.parse::<u64>().map_err(|_| format!("line {}: can't parse {} to an unsigned integer", entry.line, entry.value));
+            match entry.key.as_str() {
+                "rom" => case.rom = path(&entry.value),
+                "platform" => case.platform = entry.value.clone(),
+                "quirks" => case.quirks = Some(entry.value.clone()),
+                "cpu" => case.cpu_freq = number()? as u32,
+                "speed" => case.timers_freq = number()? as u32,
+                "seed" => case.seed = number()?,
+                "movie" => case.movie = Some(path(&entry.value)),
+                "frames" => case.frames = Some(number()?),
+                "cycles" => case.cycles = Some(number()?),
+                "expect" => case.expect = Some(path(&entry.value)),
+                "hash" => case.hash = Some(entry.value.to_lowercase()),
+                key => return Err(format!("line {}: unknown key `{}`", entry.line, key)),
+            }
+        }
+        if case.rom.is_empty() {
+            return Err("no rom".to_string());
+        }
+        if case.frames.is_none() && case.cycles.is_none() {
+            return Err("no frames or cycles to run".to_string());
+        }
+        if case.expect.is_none() && case.hash.is_none() {
+            return Err("nothing to expect, set expect or hash".to_string());
+        }
+        Ok(case)
+    }
+
+    // runs the rom and returns the final screen
+    fn run(&self) -> Result<Screenshot, String> {
+        let program = if is_assembly(&self.rom) {
+            read_assembly(&self.rom)?
+        } else {
+            read_program(&self.rom)?
+        };
+        let mut quirks = quirks::preset(&self.platform).ok_or_else(|| format!("unknown platform {}", self.platform))?;
+        if let Some(list) = &self.quirks {
+            quirks::apply(&mut quirks, list)?;
+        }
+
+        movie::reset();
+        let seed = match &self.movie {
+            Some(path) => movie::start_replay(path)?.unwrap_or(self.seed),
+            None => self.seed,
+        };
+        let chip = new_chip(&program);
+
+        let image = ImageOptions::new((Color::White, Color::DarkGrey), DIFF_SCALE);
+        let mut headless = Headless::new(chip, Debugger::default(), self.cpu_freq, self.timers_freq, image);
+        headless.cpu = Cpu::new(quirks, seed);
+        let result = match headless.run(self.cycles, self.frames) {
+            Stop::Limit | Stop::Break(_) => match movie::desync() {
+                Some(cycle) => Err(format!("movie went out of sync at cycle {}", cycle)),
+                None => Ok(Screenshot::take(&mut headless.chip)),
+            },
+            Stop::KeyWait => Err(format!("waited for a key at frame {}", headless.frame)),
+            Stop::Error(e) => Err(format!("emulation error at frame {}: {}", headless.frame, e)),
+        };
+        movie::reset();
+        result
+    }
+
+    // compares the screen with the expectations, writing the actual screen and a diff next to the expected one
+    fn check(&self, actual: &Screenshot, update: bool) -> Result<(), String> {
+        let image = ImageOptions::new((Color::White, Color::DarkGrey), DIFF_SCALE);
+        let mut failures = Vec::new();
+        if let Some(hash) = &self.hash {
+            let actual_hash = sha1_hex(actual.to_text().as_bytes());
+            if *hash != actual_hash {
+                failures.push(format!("screen hash is {}", actual_hash));
+            }
+        }
+        if let Some(path) = &self.expect {
+            if update {
+                actual.save(path, &image)?;
+            } else if !Path::new(path).exists() {
+                failures.push(format!("{} doesn't exist, run with --update to create it", path));
+            } else {
+                let expected = Screenshot::load(path)?;
+                match expected.downscale(actual.width, actual.height) {
+                    None => failures.push(format!(
+                        "screen is {}x{}, but {} is {}x{}",
+                        actual.width, actual.height, path, expected.width, expected.height
+                    )),
+                    Some(expected) if expected.count_diff(actual) > 0 => {
+                        let extension = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("png");
+                        let actual_path = format!("{}.actual.{}", path, extension);
+                        let diff_path = format!("{}.diff.png", path);
+                        actual.save(&actual_path, &image)?;
+                        std::fs::write(&diff_path, actual.diff_png(&expected, DIFF_SCALE))
+                            .map_err(|e| format!("Can't write {}: {}", diff_path, e))?;
+                        failures.push(format!(
+                            "{} pixels differ from {}, see {} and {}",
+                            expected.count_diff(actual), path, actual_path, diff_path
+                        ));
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.join("; "))
+        }
+    }
+}
+
+/// Runs every case of the manifest headless and prints their results.
+/// With `update` the expected screenshots are written instead of compared.
+pub fn run_manifest(path: &str, update: bool) -> Result<(), String> {
+    let sections = ini::read(path)?;
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let mut failed = 0;
+    let mut total = 0;
+    for section in sections.iter().filter(|s| !s.name.is_empty()) {
+        total += 1;
+        let result = Case::parse(section, dir)
+            .map_err(|e| format!("{}:{}: {}", path, section.line, e))
+            .and_then(|case| case.run().and_then(|screen| case.check(&screen, update)));
+        match result {
+            Ok(()) => println!("{} ... ok", section.name),
+            Err(e) => {
+                failed += 1;
+                println!("{} ... FAILED: {}", section.name, e);
+            }
+        }
+    }
+    println!("{} passed, {} failed", total - failed, failed);
+    if failed > 0 {
+        Err(format!("{} of {} tests failed", failed, total))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_manifest_passes() {
+        let _lock = movie::TEST_LOCK.lock().unwrap();
+        run_manifest("examples/manifest.ini", false).unwrap();
+    }
+
+    #[test]
+    fn reports_bad_cases() {
+        let section = &ini::parse("[case]\nrom = a.ch8\nframes = 10\n").unwrap()[1];
+        assert_eq!(Case::parse(section, Path::new("")).err().unwrap(), "nothing to expect, set expect or hash");
+        let section = &ini::parse("[case]\nrom = a.ch8\ncpu = fast\n").unwrap()[1];
+        assert_eq!(Case::parse(section, Path::new("")).err().unwrap(), "line 3: can't parse fast to an unsigned integer");
+    }
+}
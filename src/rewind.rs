@@ -0,0 +1,206 @@
+use chiprust_emu::Chip8;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::snapshot::Snapshot;
+
+// bookkeeping cost of a delta and of every changed run, used for the memory budget
+const DELTA_OVERHEAD: usize = 32;
+const RUN_OVERHEAD: usize = 32;
+
+/// Changed byte runs that turn one serialized snapshot into another.
+struct Delta {
+    len: usize,
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    fn between(from: &[u8], to: &[u8]) -> Delta {
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        for (i, &byte) in to.iter().enumerate() {
+            if from.get(i) == Some(&byte) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() == i => bytes.push(byte),
+                _ => runs.push((i, vec![byte])),
+            }
+        }
+        Delta { len: to.len(), runs }
+    }
+
+    fn apply(&self, from: &[u8]) -> Vec<u8> {
+        let mut result = from.to_vec();
+        result.resize(self.len, 0);
+        for (start, bytes) in &self.runs {
+            result[*start..*start + bytes.len()].copy_from_slice(bytes);
+        }
+        result
+    }
+
+    fn size(&self) -> usize {
+        DELTA_OVERHEAD + self.runs.iter().map(|(_, bytes)| RUN_OVERHEAD + bytes.len()).sum::<usize>()
+    }
+}
+
+/// Ring buffer of past states, recorded by the scheduler every few frames and after every stepped instruction.
+/// In turbo mode frames are recorded at the rate they would be without it, in real time.
+///
+/// Only the newest snapshot is kept whole, every older one is stored as a delta
+/// against the snapshot after it, so most of the memory and display isn't repeated.
+pub struct Rewind {
+    budget: usize,
+    interval: u32,
+    counter: u32,
+    latest: Option<Vec<u8>>,
+    recorded_at: Option<Instant>,
+    // the back turns `latest` into the previous snapshot
+    deltas: VecDeque<Delta>,
+    size: usize,
+}
+
+impl Rewind {
+    /// Budget is in bytes, zero disables recording.
+    pub fn new(budget: usize, interval: u32) -> Rewind {
+        Rewind {
+            budget,
+            interval: interval.max(1),
+            counter: 0,
+            latest: None,
+            recorded_at: None,
+            deltas: VecDeque::new(),
+            size: 0,
+        }
+    }
+
+    /// Called after every frame, records one in `interval` of them.
+    pub fn record_frame(&mut self, chip: &mut Chip8) {
+        self.counter += 1;
+        if self.counter >= self.interval {
+            self.record(chip);
+        }
+    }
+
+    /// Called after every frame in turbo mode instead, records as many frames per second of real time
+    /// as `record_frame` does at the usual frame rate, so turbo doesn't use up the budget any faster.
+    pub fn record_turbo_frame(&mut self, chip: &mut Chip8, frame_rate: u32) {
+        let period = Duration::from_secs(self.interval as u64) / frame_rate.max(1);
+        // not `Option::is_none_or`, which needs rust 1.82
+        let due = match self.recorded_at {
+            Some(at) => at.elapsed() >= period,
+            None => true,
+        };
+        if due {
+            self.record(chip);
+        }
+    }
+
+    /// Records the current state.
+    pub fn record(&mut self, chip: &mut Chip8) {
+        if self.budget == 0 {
+            return;
+        }
+        self.counter = 0;
+        self.recorded_at = Some(Instant::now());
+        let bytes = Snapshot::take(chip).to_bytes();
+        if self.latest.as_ref() == Some(&bytes) {
+            return;
+        }
+        if let Some(latest) = &self.latest {
+            let delta = Delta::between(&bytes, latest);
+            self.size += delta.size();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(bytes);
+        while self.size > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.size -= delta.size(),
+                None => break,
+            }
+        }
+    }
+
+    /// Restores the state `count` snapshots back, or the oldest one if there are less.
+    /// Returns how many snapshots were stepped back.
+    pub fn rewind(&mut self, chip: &mut Chip8, count: usize) -> Result<usize, String> {
+        let mut latest = match self.latest.take() {
+            Some(v) => v,
+            None => return Ok(0),
+        };
+        let mut stepped = 0;
+        while stepped < count {
+            match self.deltas.pop_back() {
+                Some(delta) => {
+                    latest = delta.apply(&latest);
+                    self.size -= delta.size();
+                    stepped += 1;
+                }
+                None => break,
+            }
+        }
+        let result = match stepped {
+            0 => Ok(()),
+            // the buffer only ever contains snapshots it wrote itself
+            _ => Snapshot::from_bytes(&latest).unwrap().restore(chip),
+        };
+        self.latest = Some(latest);
+        self.counter = 0;
+        result.map(|_| stepped)
+    }
+
+    /// Number of snapshots that can be stepped back.
+    pub fn available(&self) -> usize {
+        self.deltas.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip() -> Chip8 {
+        let mut chip = Chip8::new
+            ::<&'static (dyn Fn() -> u8 + Send + Sync + 'static),
+            &'static (dyn Fn(u8) -> bool + Send + Sync + 'static)>
+            (&|| 0, &|_| false);
+        // counts up in V0 forever
+        chip.load(0x200, &[0x70, 0x01, 0x12, 0x00], None);
+        chip
+    }
+
+    #[test]
+    fn records_every_interval_frames_and_steps_back() {
+        let mut chip = chip();
+        let mut rewind = Rewind::new(1 << 20, 2);
+        rewind.record(&mut chip);
+        for _ in 0..6 {
+            chip.cpu_tick().unwrap();
+            rewind.record_frame(&mut chip);
+        }
+        // unchanged states aren't recorded twice
+        rewind.record(&mut chip);
+        assert_eq!(rewind.available(), 3);
+        assert_eq!(rewind.rewind(&mut chip, 1), Ok(1));
+        assert_eq!(chip.get_regs()[0], 2);
+        assert_eq!(rewind.rewind(&mut chip, 5), Ok(2));
+        assert_eq!((chip.get_regs()[0], chip.get_pc()), (0, 0x200));
+        assert_eq!(rewind.rewind(&mut chip, 1), Ok(0));
+    }
+
+    #[test]
+    fn turbo_frames_are_recorded_in_real_time() {
+        let mut chip = chip();
+        let mut rewind = Rewind::new(1 << 20, 1);
+        rewind.record(&mut chip);
+        // a second per frame at 1 frame per second, far longer than this takes
+        for _ in 0..10 {
+            chip.cpu_tick().unwrap();
+            rewind.record_turbo_frame(&mut chip, 1);
+        }
+        assert_eq!(rewind.available(), 0);
+        std::thread::sleep(Duration::from_millis(2));
+        chip.cpu_tick().unwrap();
+        rewind.record_turbo_frame(&mut chip, 1000);
+        assert_eq!(rewind.available(), 1);
+    }
+}
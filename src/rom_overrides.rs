@@ -0,0 +1,123 @@
+use crate::ini;
+
+const DATABASE: &str = include_str!("rom_overrides.ini");
+const ROM_SECTION_PREFIX: &str = "rom ";
+
+/// Settings built into the binary for a rom, found by its hash.
+#[derive(Default)]
+pub struct RomOverrides {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub platform: Option<String>,
+    pub cycles_per_frame: Option<u32>,
+    // keypad keys in the `5=Up,8=Down` format
+    pub keys: Option<String>,
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+}
+
+impl RomOverrides {
+    /// Settings as argument values, by argument name. The cpu frequency depends on the speed, see `cpu_freq`.
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        let mut settings = Vec::new();
+        if let Some(platform) = &self.platform {
+            settings.push(("platform", platform.clone()));
+        }
+        if let Some(color) = &self.foreground {
+            settings.push(("foreground", color.clone()));
+        }
+        if let Some(color) = &self.background {
+            settings.push(("background", color.clone()));
+        }
+        settings
+    }
+
+    /// Instructions per second at the speed, the frames per second.
+    pub fn cpu_freq(&self, timers_freq: u32) -> Option<u32> {
+        // turbo mode still runs 60 frames per emulated second
+        let frames = match timers_freq {
+            0 => 60,
+            v => v,
+        };
+        self.cycles_per_frame.map(|cycles| cycles * frames)
+    }
+
+    /// Human readable summary for `--info`.
+    pub fn describe(&self) -> String {
+        let fields = [
+            ("title", self.title.clone()),
+            ("author", self.author.clone()),
+            ("platform", self.platform.clone()),
+            ("cycles per frame", self.cycles_per_frame.map(|v| v.to_string())),
+            ("keys", self.keys.clone()),
+            ("foreground", self.foreground.clone()),
+            ("background", self.background.clone()),
+        ];
+        fields
+            .iter()
+            .filter_map(|(name, value)| value.as_ref().map(|v| format!("{}: {}\n", name, v)))
+            .collect()
+    }
+}
+
+/// Looks up a rom by its lowercase hex SHA-1.
+pub fn lookup(sha1: &str) -> Option<RomOverrides> {
+    // the database is part of the binary, so it always parses
+    let sections = ini::parse(DATABASE).unwrap();
+    let section = sections
+        .iter()
+        .find(|s| s.name.strip_prefix(ROM_SECTION_PREFIX).map(str::to_lowercase).as_deref() == Some(sha1))?;
+    let mut overrides = RomOverrides::default();
+    for entry in &section.entries {
+        let value = Some(entry.value.clone());
+        match entry.key.as_str() {
+            "title" => overrides.title = value,
+            "author" => overrides.author = value,
+            "platform" => overrides.platform = value,
+            "cycles_per_frame" => overrides.cycles_per_frame = entry.value.parse().ok(),
+            "keys" => overrides.keys = value,
+            "foreground" => overrides.foreground = value,
+            "background" => overrides.background = value,
+            _ => {}
+        }
+    }
+    Some(overrides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::sha1::sha1_hex;
+
+    #[test]
+    fn finds_the_example_rom() {
+        let rom = assemble(include_str!("../examples/c8-logo.asm")).unwrap();
+        let overrides = lookup(&sha1_hex(&rom)).unwrap();
+        assert_eq!(overrides.title.as_deref(), Some("C8 logo"));
+        assert!(overrides.settings().contains(&("platform", "chip8".to_string())));
+        assert_eq!(overrides.cpu_freq(60), Some(600));
+    }
+
+    #[test]
+    fn cycles_per_frame_follow_the_speed() {
+        let overrides = RomOverrides { cycles_per_frame: Some(10), ..RomOverrides::default() };
+        assert_eq!(overrides.cpu_freq(30), Some(300));
+        assert_eq!(overrides.cpu_freq(0), Some(600));
+        assert_eq!(RomOverrides::default().cpu_freq(60), None);
+    }
+
+    #[test]
+    fn unknown_roms_are_not_found() {
+        assert!(lookup(&sha1_hex(b"\x12\x00")).is_none());
+    }
+
+    #[test]
+    fn every_section_is_a_rom_hash() {
+        // the section before the first header holds nothing but comments
+        for section in ini::parse(DATABASE).unwrap().iter().filter(|s| s.line > 0) {
+            let hash = section.name.strip_prefix(ROM_SECTION_PREFIX).unwrap();
+            assert!(hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit()), "{}", section.name);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::{atomic::{AtomicBool, Ordering}, Condvar, Mutex};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RunState {
+    Running,
+    Paused,
+    // execute this many more instructions, then pause
+    Step(u32),
+    // run until the next timers tick, then pause
+    StepFrame,
+}
+
+pub struct RunControl {
+    state: Mutex<RunState>,
+    // why execution was paused, if not by the user
+    reason: Mutex<Option<String>>,
+    changed: Condvar,
+    pub step_count: u32,
+    // keys shown while paused, they depend on the keymap
+    pause_hint: String,
+    // run frames as fast as possible instead of at the timers frequency
+    turbo: AtomicBool,
+}
+
+impl RunControl {
+    pub fn new(step_count: u32, pause_hint: String) -> RunControl {
+        RunControl {
+            state: Mutex::new(RunState::Running),
+            reason: Mutex::new(None),
+            changed: Condvar::new(),
+            step_count,
+            pause_hint,
+            turbo: AtomicBool::new(false),
+        }
+    }
+
+    pub fn state(&self) -> RunState {
+        *self.state.lock().unwrap()
+    }
+
+    fn set(&self, state: RunState) {
+        *self.reason.lock().unwrap() = None;
+        *self.state.lock().unwrap() = state;
+        self.changed.notify_all();
+    }
+
+    pub fn pause(&self) {
+        self.set(RunState::Paused)
+    }
+
+    /// Pauses and shows the reason in the status label until execution continues.
+    pub fn pause_with_reason(&self, reason: String) {
+        self.set(RunState::Paused);
+        *self.reason.lock().unwrap() = Some(reason);
+    }
+
+    pub fn resume(&self) {
+        self.set(RunState::Running)
+    }
+
+    pub fn toggle_pause(&self) {
+        match self.state() {
+            RunState::Running => self.pause(),
+            _ => self.resume(),
+        }
+    }
+
+    /// Executes `n` instructions and pauses. Does nothing unless paused.
+    pub fn step(&self, n: u32) {
+        let mut state = self.state.lock().unwrap();
+        if *state == RunState::Paused && n > 0 {
+            *self.reason.lock().unwrap() = None;
+            *state = RunState::Step(n);
+            self.changed.notify_all();
+        }
+    }
+
+    /// Runs until the timers tick once and pauses. Does nothing unless paused.
+    pub fn step_frame(&self) {
+        let mut state = self.state.lock().unwrap();
+        if *state == RunState::Paused {
+            *self.reason.lock().unwrap() = None;
+            *state = RunState::StepFrame;
+            self.changed.notify_all();
+        }
+    }
+
+    pub fn is_turbo(&self) -> bool {
+        self.turbo.load(Ordering::Relaxed)
+    }
+
+    pub fn set_turbo(&self, turbo: bool) {
+        self.turbo.store(turbo, Ordering::Relaxed)
+    }
+
+    pub fn toggle_turbo(&self) {
+        self.turbo.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    /// Blocks the emulation thread until it is allowed to execute an instruction.
+    pub fn wait_cpu(&self) {
+        let mut state = self.state.lock().unwrap();
+        while *state == RunState::Paused {
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Called by the emulation thread after every executed instruction.
+    pub fn instruction_done(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let RunState::Step(n) = *state {
+            *state = if n > 1 { RunState::Step(n - 1) } else { RunState::Paused };
+            self.changed.notify_all();
+        }
+    }
+
+    /// Called by the emulation thread after every timers tick.
+    pub fn frame_done(&self) {
+        let mut state = self.state.lock().unwrap();
+        if *state == RunState::StepFrame {
+            *state = RunState::Paused;
+            self.changed.notify_all();
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self.state() {
+            RunState::Running if self.is_turbo() => "TURBO".to_string(),
+            RunState::Running => "running".to_string(),
+            RunState::Paused => match &*self.reason.lock().unwrap() {
+                Some(reason) => format!("PAUSED: {}", reason),
+                None => format!("PAUSED ({})", self.pause_hint),
+            },
+            RunState::Step(_) | RunState::StepFrame => "stepping".to_string(),
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use chiprust_emu::{Chip8, Chip8State};
+use std::sync::{Arc, Mutex, atomic::Ordering};
+use std::{thread, time::{Duration, Instant}};
+use spin_sleep::LoopHelper;
+
+use crate::{CYCLE_RATE, SPEED};
+use crate::audio::{AudioOut, Voice};
+use crate::cpu::Cpu;
+use crate::debugger::Debugger;
+use crate::movie;
+use crate::rewind::Rewind;
+use crate::run_control::{RunControl, RunState};
+use crate::snapshot::copy_state;
+use crate::termui;
+
+// how long turbo mode runs frames before publishing one, well under a drawn frame
+const TURBO_BATCH: Duration = Duration::from_millis(10);
+const REPORT_INTERVAL: Duration = Duration::from_millis(500);
+// instructions per frame of an uncapped cpu, a good deal more than any rom needs
+const UNCAPPED_CYCLES: u64 = 20000;
+
+/// The machine as the renderer shows it, published after every frame.
+pub struct Frame {
+    pub state: Chip8State,
+    pub display: [u128; 64],
+    pub hires: bool,
+    // whether the display changed since the renderer last took a frame
+    pub dirty: bool,
+}
+
+impl Frame {
+    pub fn of(chip: &mut Chip8) -> Frame {
+        Frame {
+            state: chip.to_state(),
+            dirty: chip.display.dirty(),
+            // taken by this frame, the next one is only dirty if the display changed again
+            display: *chip.display.read(),
+            hires: chip.display.hi_res(),
+        }
+    }
+}
+
+impl Clone for Frame {
+    fn clone(&self) -> Frame {
+        Frame {
+            state: copy_state(&self.state),
+            ..*self
+        }
+    }
+}
+
+/// The latest frame, so the renderer never has to wait for the emulation to draw.
+pub struct FrameSlot(Mutex<Frame>);
+
+impl FrameSlot {
+    pub fn new(frame: Frame) -> FrameSlot {
+        FrameSlot(Mutex::new(frame))
+    }
+
+    /// Replaces the frame, the display stays dirty until the renderer takes one.
+    pub fn publish(&self, frame: Frame) {
+        let mut latest = self.0.lock().unwrap();
+        let dirty = latest.dirty || frame.dirty;
+        *latest = frame;
+        latest.dirty = dirty;
+    }
+
+    pub fn take(&self) -> Frame {
+        let mut latest = self.0.lock().unwrap();
+        let frame = latest.clone();
+        latest.dirty = false;
+        frame
+    }
+}
+
+/// Counts instructions per frame. The remainder of dividing the frequencies is carried from frame to frame,
+/// so the cpu runs at its frequency also if it isn't a multiple of the timers frequency.
+/// A cpu frequency of zero runs `UNCAPPED_CYCLES` in every frame.
+#[derive(Clone, Copy)]
+pub struct FrameClock {
+    cpu_freq: u64,
+    timers_freq: u64,
+    // frames finished so far
+    frame: u64,
+}
+
+impl FrameClock {
+    pub fn new(cpu_freq: u32, timers_freq: u32) -> FrameClock {
+        // unlimited timers still tick 60 times per emulated second
+        let timers_freq = match timers_freq {
+            0 => 60,
+            v => v as u64,
+        };
+        FrameClock {
+            cpu_freq: match cpu_freq {
+                0 => UNCAPPED_CYCLES * timers_freq,
+                v => v as u64,
+            },
+            timers_freq,
+            frame: 0,
+        }
+    }
+
+    /// Instructions in the current frame, zero in some frames if the cpu is slower than the timers.
+    pub fn cycles(&self) -> u64 {
+        let start = self.frame * self.cpu_freq / self.timers_freq;
+        (self.frame + 1) * self.cpu_freq / self.timers_freq - start
+    }
+
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Average instructions per frame.
+    pub fn ratio(&self) -> f64 {
+        self.cpu_freq as f64 / self.timers_freq as f64
+    }
+}
+
+/// Runs the emulation on a single thread, frame by frame: a fixed number of instructions, then one timers tick.
+/// The ratio of instructions to timer ticks doesn't depend on thread scheduling, also while stepping.
+pub struct Scheduler {
+    pub chip: Arc<Mutex<Chip8>>,
+    pub run_control: Arc<RunControl>,
+    pub debugger: Arc<Mutex<Debugger>>,
+    pub rewind: Arc<Mutex<Rewind>>,
+    pub frames: Arc<FrameSlot>,
+    pub audio_out: Option<AudioOut>,
+    pub cpu: Cpu,
+    pub timers_freq: u32,
+    pub clock: FrameClock,
+    // instructions executed in the current frame, 0 to start at a frame boundary
+    pub frame_cycle: u64,
+}
+
+impl Scheduler {
+    // frames per second outside of turbo mode, zero only means turbo, turning it off goes back to the usual rate
+    fn normal_rate(&self) -> u32 {
+        match self.timers_freq {
+            0 => 60,
+            v => v,
+        }
+    }
+
+    // executes instructions until the frame ends or run control pauses, tells whether the frame ended
+    fn run_frame(&mut self, chip: &mut Chip8) -> bool {
+        let (debugger, rewind) = (self.debugger.clone(), self.rewind.clone());
+        let mut debugger = debugger.lock().unwrap();
+        let mut rewind = rewind.lock().unwrap();
+        while self.run_control.state() != RunState::Paused {
+            // stepped instructions can be stepped back one by one, running ones a few frames at a time
+            let stepping = matches!(self.run_control.state(), RunState::Step(_));
+            if self.clock.cycles() == 0 {
+                self.end_frame(chip, &mut rewind, stepping);
+                return true;
+            }
+            let wait;
+            if debugger.is_active() {
+                let before = chip.to_state();
+                if let Some(reason) = debugger.check_before(&before) {
+                    self.run_control.pause_with_reason(reason);
+                    rewind.record(chip);
+                    return false;
+                }
+                wait = self.cpu.tick(chip).unwrap();
+                if let Some(reason) = debugger.check_after(&before, &chip.to_state()) {
+                    self.run_control.pause_with_reason(reason);
+                }
+            } else {
+                wait = self.cpu.tick(chip).unwrap();
+            }
+            movie::CYCLE.fetch_add(1, Ordering::Relaxed);
+            if stepping {
+                rewind.record(chip);
+            }
+            self.run_control.instruction_done();
+
+            self.frame_cycle += 1;
+            if self.frame_cycle >= self.clock.cycles() || wait {
+                self.end_frame(chip, &mut rewind, stepping);
+                return true;
+            }
+        }
+        // paused in the middle of a frame, stepping back starts from here
+        rewind.record(chip);
+        false
+    }
+
+    fn end_frame(&mut self, chip: &mut Chip8, rewind: &mut Rewind, stepping: bool) {
+        self.frame_cycle = 0;
+        self.clock.next_frame();
+        self.tick_timers(chip);
+        if stepping {
+            // recorded after every instruction already
+        } else if self.run_control.is_turbo() {
+            rewind.record_turbo_frame(chip, self.normal_rate());
+        } else {
+            rewind.record_frame(chip);
+        }
+        self.run_control.frame_done();
+    }
+
+    fn tick_timers(&mut self, chip: &mut Chip8) {
+        // taken before the tick, a sound timer set to N beeps for N ticks
+        let voice = Voice::of(chip, &self.cpu);
+        chip.timers_tick();
+        if let Some(audio_out) = self.audio_out.as_mut() {
+            if let Err(e) = audio_out.tick(&voice) {
+                termui::exit(&e)
+            }
+        }
+        #[cfg(feature = "sound")]
+        crate::audio::push_voice(&voice);
+    }
+
+    pub fn run(mut self) {
+        let chip = self.chip.clone();
+        {
+            let mut chip = chip.lock().unwrap();
+            // keys go through the movie module, which records or replays them if asked to
+            chip.set_handlers(&movie::key_wait_handler, &movie::key_state_handler);
+            // the initial state, so the first instruction can be stepped back too
+            self.rewind.lock().unwrap().record(&mut chip);
+        }
+
+        let normal_rate = self.normal_rate();
+        let mut loop_helper = LoopHelper::builder()
+            .build_with_target_rate(normal_rate);
+        let mut frames = 0u64;
+        let mut report_start = Instant::now();
+
+        loop {
+            self.run_control.wait_cpu();
+            loop_helper.loop_start();
+            {
+                let mut chip = chip.lock().unwrap();
+                // in turbo mode frames run in batches, the chip is only published and unlocked between them
+                let batch_end = Instant::now() + TURBO_BATCH;
+                loop {
+                    if self.run_frame(&mut chip) {
+                        frames += 1;
+                    }
+                    let turbo = self.run_control.is_turbo() && self.run_control.state() == RunState::Running;
+                    if !turbo || Instant::now() >= batch_end {
+                        break;
+                    }
+                }
+                self.frames.publish(Frame::of(&mut chip));
+            }
+
+            let elapsed = report_start.elapsed();
+            if elapsed >= REPORT_INTERVAL {
+                let fps = frames as f64 / elapsed.as_secs_f64();
+                unsafe {
+                    CYCLE_RATE = fps * self.clock.ratio();
+                    SPEED = fps / normal_rate as f64;
+                }
+                frames = 0;
+                report_start = Instant::now();
+            }
+
+            match self.run_control.state() {
+                // the draw thread gets its turn at the locks
+                RunState::Running if self.run_control.is_turbo() => thread::yield_now(),
+                RunState::Running => loop_helper.loop_sleep(),
+                // steps run as fast as they can
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(cpu_freq: u32, timers_freq: u32, count: usize) -> Vec<u64> {
+        let mut clock = FrameClock::new(cpu_freq, timers_freq);
+        (0..count)
+            .map(|_| {
+                let cycles = clock.cycles();
+                clock.next_frame();
+                cycles
+            })
+            .collect()
+    }
+
+    #[test]
+    fn carries_the_remainder_between_frames() {
+        assert_eq!(frames(1000, 60, 60).iter().sum::<u64>(), 1000);
+        assert_eq!(frames(1000, 60, 3), vec![16, 17, 17]);
+        assert_eq!(frames(30, 60, 4), vec![0, 1, 0, 1]);
+        assert_eq!(frames(600, 60, 2), vec![10, 10]);
+        assert_eq!(frames(600, 0, 1), vec![10]);
+        assert_eq!(frames(0, 50, 2), vec![UNCAPPED_CYCLES, UNCAPPED_CYCLES]);
+    }
+}
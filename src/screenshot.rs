@@ -0,0 +1,490 @@
+use chiprust_emu::{display::get_px, Chip8};
+use crossterm::style::Color;
+use std::fs;
+use std::path::Path;
+
+use crate::snapshot::read_display;
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+// biggest stored deflate block
+const MAX_BLOCK: usize = 65535;
+// plain pbm lines are at most 70 characters long, a pixel and a space each
+const PBM_LINE_PIXELS: usize = 35;
+
+/// How screenshots are rendered to png.
+#[derive(Clone, Copy)]
+pub struct ImageOptions {
+    // unlit and lit pixels
+    pub palette: [[u8; 3]; 2],
+    // every pixel becomes a square this big
+    pub scale: usize,
+}
+
+impl ImageOptions {
+    pub fn new(screen_colors: (Color, Color), scale: usize) -> ImageOptions {
+        ImageOptions {
+            palette: [color_rgb(screen_colors.1), color_rgb(screen_colors.0)],
+            scale: scale.max(1),
+        }
+    }
+}
+
+// the usual xterm values, terminals are free to show them differently
+fn color_rgb(color: Color) -> [u8; 3] {
+    const ANSI: [[u8; 3]; 16] = [
+        [0, 0, 0], [128, 0, 0], [0, 128, 0], [128, 128, 0], [0, 0, 128], [128, 0, 128], [0, 128, 128], [192, 192, 192],
+        [128, 128, 128], [255, 0, 0], [0, 255, 0], [255, 255, 0], [0, 0, 255], [255, 0, 255], [0, 255, 255], [255, 255, 255],
+    ];
+    match color {
+        Color::Black | Color::Reset => ANSI[0],
+        Color::DarkRed => ANSI[1],
+        Color::DarkGreen => ANSI[2],
+        Color::DarkYellow => ANSI[3],
+        Color::DarkBlue => ANSI[4],
+        Color::DarkMagenta => ANSI[5],
+        Color::DarkCyan => ANSI[6],
+        Color::Grey => ANSI[7],
+        Color::DarkGrey => ANSI[8],
+        Color::Red => ANSI[9],
+        Color::Green => ANSI[10],
+        Color::Yellow => ANSI[11],
+        Color::Blue => ANSI[12],
+        Color::Magenta => ANSI[13],
+        Color::Cyan => ANSI[14],
+        Color::White => ANSI[15],
+        Color::Rgb { r, g, b } => [r, g, b],
+        Color::AnsiValue(n) if n < 16 => ANSI[n as usize],
+        Color::AnsiValue(n) if n < 232 => {
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            let n = n - 16;
+            [level(n / 36), level(n / 6 % 6), level(n % 6)]
+        }
+        Color::AnsiValue(n) => [8 + (n - 232) * 10; 3],
+    }
+}
+
+/// The screen in its current resolution, 128x64 in hires mode and 64x32 in lores mode.
+pub struct Screenshot {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<bool>,
+}
+
+impl Screenshot {
+    pub fn new(display: &[u128; 64], hires: bool) -> Screenshot {
+        // lores pixels are drawn as 2x2 squares, so every other one is enough
+        let step = if hires { 1 } else { 2 };
+        let (width, height) = (128 / step, 64 / step);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(get_px(display, x * step, y * step));
+            }
+        }
+        Screenshot { width, height, pixels }
+    }
+
+    /// The chip's screen, the renderer still redraws it if it was due to.
+    pub fn take(chip: &mut Chip8) -> Screenshot {
+        Screenshot::new(&read_display(chip), chip.display.hi_res())
+    }
+
+    /// Whether the pixel is lit.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x]
+    }
+
+    /// One `#` for every lit and `.` for every unlit pixel, a line per row.
+    pub fn to_text(&self) -> String {
+        let mut result = String::with_capacity((self.width + 1) * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                result.push(if self.get(x, y) { '#' } else { '.' });
+            }
+            result.push('\n');
+        }
+        result
+    }
+
+    /// Plain pbm, lit pixels are 1 (black).
+    pub fn to_pbm(&self) -> String {
+        let mut result = format!("P1\n{} {}\n", self.width, self.height);
+        for y in 0..self.height {
+            let row: Vec<&str> = (0..self.width).map(|x| if self.get(x, y) { "1" } else { "0" }).collect();
+            for line in row.chunks(PBM_LINE_PIXELS) {
+                result.push_str(&line.join(" "));
+                result.push('\n');
+            }
+        }
+        result
+    }
+
+    /// Two color png, scaled up by the options.
+    pub fn to_png(&self, options: &ImageOptions) -> Vec<u8> {
+        let scale = options.scale;
+        indexed_png(self.width * scale, self.height * scale, 1, &options.palette, |x, y| {
+            self.get(x / scale, y / scale) as u8
+        })
+    }
+
+    /// Png showing where the screenshot differs from the expected one, which has the same size:
+    /// pixels lit in both are white, only in the expected one red and only in this one green.
+    pub fn diff_png(&self, expected: &Screenshot, scale: usize) -> Vec<u8> {
+        let palette = [[0, 0, 0], [255, 255, 255], [255, 0, 0], [0, 255, 0]];
+        indexed_png(self.width * scale, self.height * scale, 2, &palette, |x, y| {
+            match (expected.get(x / scale, y / scale), self.get(x / scale, y / scale)) {
+                (false, false) => 0,
+                (true, true) => 1,
+                (true, false) => 2,
+                (false, true) => 3,
+            }
+        })
+    }
+
+    /// Number of pixels that differ from a screenshot of the same size.
+    pub fn count_diff(&self, other: &Screenshot) -> usize {
+        self.pixels.iter().zip(&other.pixels).filter(|(a, b)| a != b).count()
+    }
+
+    /// Samples a screenshot scaled up by an integer factor back to the given size.
+    pub fn downscale(&self, width: usize, height: usize) -> Option<Screenshot> {
+        let scale = self.width / width;
+        if scale == 0 || self.width != width * scale || self.height != height * scale {
+            return None;
+        }
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(self.get(x * scale, y * scale));
+            }
+        }
+        Some(Screenshot { width, height, pixels })
+    }
+
+    /// Reads a screenshot written by `save`. Pngs are only read if this emulator wrote them,
+    /// their pixels come out scaled up as they were saved.
+    pub fn load(path: &str) -> Result<Screenshot, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+        let extension = Path::new(path).extension().and_then(|e| e.to_str()).map(str::to_lowercase);
+        let result = match extension.as_deref() {
+            Some("png") => from_png(&bytes),
+            Some("pbm") => from_pbm(&String::from_utf8_lossy(&bytes)),
+            Some("txt") => from_text(&String::from_utf8_lossy(&bytes)),
+            _ => Err("expected a .png, .pbm or .txt file".to_string()),
+        };
+        result.map_err(|e| format!("Can't load {}: {}", path, e))
+    }
+
+    /// Writes the screenshot in the format given by the extension: `.png`, `.pbm` or `.txt`.
+    pub fn save(&self, path: &str, options: &ImageOptions) -> Result<(), String> {
+        let extension = Path::new(path).extension().and_then(|e| e.to_str()).map(str::to_lowercase);
+        let bytes = match extension.as_deref() {
+            Some("png") => self.to_png(options),
+            Some("pbm") => self.to_pbm().into_bytes(),
+            Some("txt") => self.to_text().into_bytes(),
+            _ => return Err(format!("Can't save a screenshot to {}, expected a .png, .pbm or .txt file.", path)),
+        };
+        fs::write(path, bytes).map_err(|e| format!("Can't write {}: {}", path, e))
+    }
+}
+
+fn from_text(text: &str) -> Result<Screenshot, String> {
+    let rows: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+    let width = rows.first().map_or(0, |r| r.chars().count());
+    let mut pixels = Vec::with_capacity(width * rows.len());
+    for row in &rows {
+        if row.chars().count() != width {
+            return Err("rows have different lengths".to_string());
+        }
+        for c in row.chars() {
+            match c {
+                '#' => pixels.push(true),
+                '.' => pixels.push(false),
+                _ => return Err(format!("unexpected character `{}`", c)),
+            }
+        }
+    }
+    Ok(Screenshot { width, height: rows.len(), pixels })
+}
+
+fn from_pbm(text: &str) -> Result<Screenshot, String> {
+    let mut tokens = text.lines().map(|l| l.split('#').next().unwrap()).flat_map(str::split_whitespace);
+    if tokens.next() != Some("P1") {
+        return Err("not a plain pbm image".to_string());
+    }
+    let mut size = || tokens.next().and_then(|v| v.parse::<usize>().ok()).ok_or("bad pbm size");
+    let (width, height) = (size()?, size()?);
+    // pixels don't have to be separated in plain pbm
+    let pixels: Vec<bool> = tokens.flat_map(str::chars).map(|c| c == '1').collect();
+    if pixels.len() != width * height {
+        return Err("pbm image is truncated".to_string());
+    }
+    Ok(Screenshot { width, height, pixels })
+}
+
+fn from_png(bytes: &[u8]) -> Result<Screenshot, String> {
+    const UNSUPPORTED: &str = "only 1 bit pngs saved by chiprust-emu-cli can be read, try .pbm or .txt";
+    if bytes.len() < 8 || &bytes[..8] != PNG_SIGNATURE {
+        return Err("not a png image".to_string());
+    }
+    let (mut width, mut height) = (0usize, 0usize);
+    let mut zlib = Vec::new();
+    let mut pos = 8;
+    while pos + 12 <= bytes.len() {
+        let len = u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]) as usize;
+        // the crc isn't checked, but has to be there
+        let data = &bytes.get(pos + 8..pos + 12 + len).ok_or("png image is truncated")?[..len];
+        match &bytes[pos + 4..pos + 8] {
+            b"IHDR" => {
+                if data.len() != 13 || data[8..] != [1, 3, 0, 0, 0] {
+                    return Err(UNSUPPORTED.to_string());
+                }
+                width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+                height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+            }
+            b"IDAT" => zlib.extend_from_slice(data),
+            _ => {}
+        }
+        pos += 12 + len;
+    }
+
+    // only stored deflate blocks, as zlib_stored writes them
+    let mut data = Vec::new();
+    let mut pos = 2;
+    loop {
+        let header = *zlib.get(pos).ok_or("png image is truncated")?;
+        if header & 0b110 != 0 {
+            return Err(UNSUPPORTED.to_string());
+        }
+        let len = zlib.get(pos + 1..pos + 3).ok_or("png image is truncated")?;
+        let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+        data.extend_from_slice(zlib.get(pos + 5..pos + 5 + len).ok_or("png image is truncated")?);
+        pos += 5 + len;
+        if header & 1 != 0 {
+            break;
+        }
+    }
+
+    let row_len = 1 + width.div_ceil(8);
+    if data.len() != row_len * height {
+        return Err("png image is truncated".to_string());
+    }
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in data.chunks(row_len) {
+        if row[0] != 0 {
+            return Err(UNSUPPORTED.to_string());
+        }
+        for x in 0..width {
+            pixels.push(row[1 + x / 8] & (0x80 >> (x % 8)) != 0);
+        }
+    }
+    Ok(Screenshot { width, height, pixels })
+}
+
+// palette png of `bits` per pixel, with the palette index of every pixel given by the function
+fn indexed_png(width: usize, height: usize, bits: usize, palette: &[[u8; 3]], pixel: impl Fn(usize, usize) -> u8) -> Vec<u8> {
+    // rows of packed palette indices, each after a "no filter" byte
+    let per_byte = 8 / bits;
+    let row_len = 1 + width.div_ceil(per_byte);
+    let mut data = Vec::with_capacity(row_len * height);
+    for y in 0..height {
+        data.push(0);
+        let mut byte = 0u8;
+        for x in 0..width {
+            byte |= pixel(x, y) << (8 - bits * (x % per_byte + 1));
+            if x % per_byte == per_byte - 1 || x == width - 1 {
+                data.push(byte);
+                byte = 0;
+            }
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // indexed color, default compression, filtering and no interlace
+    header.extend_from_slice(&[bits as u8, 3, 0, 0, 0]);
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"PLTE", &palette.concat());
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&data));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// screenshots are small, so the data is stored without compressing it
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut result = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        result.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        result.push(blocks.peek().is_none() as u8);
+        result.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        result.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        result.extend_from_slice(block);
+    }
+    result.extend_from_slice(&adler32(data).to_be_bytes());
+    result
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// First `<rom>-N.<extension>` file that doesn't exist yet, for the screenshot and record hotkeys.
+pub fn next_path(source: &str, extension: &str) -> String {
+    let mut n = 1;
+    loop {
+        let path = format!("{}-{}.{}", source, n, extension);
+        if !Path::new(&path).exists() {
+            return path;
+        }
+        n += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(width: usize, height: usize, lit: impl Fn(usize, usize) -> bool) -> Screenshot {
+        let pixels = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| lit(x, y)).collect();
+        Screenshot { width, height, pixels }
+    }
+
+    // chunks of a png as (type, data), checking their crcs
+    fn chunks(png: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        assert_eq!(&png[..8], PNG_SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]]) as usize;
+            let crc = u32::from_be_bytes([png[pos + 8 + len], png[pos + 9 + len], png[pos + 10 + len], png[pos + 11 + len]]);
+            assert_eq!(crc32(&png[pos + 4..pos + 8 + len]), crc);
+            chunks.push((png[pos + 4..pos + 8].to_vec(), png[pos + 8..pos + 8 + len].to_vec()));
+            pos += 12 + len;
+        }
+        chunks
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn writes_scaled_png() {
+        let options = ImageOptions { palette: [[0, 0, 0], [0x12, 0x34, 0x56]], scale: 3 };
+        let png = screen(64, 32, |x, y| x == y).to_png(&options);
+        let chunks = chunks(&png);
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| &kind[..]).collect();
+        assert_eq!(kinds, vec![&b"IHDR"[..], b"PLTE", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 192, 0, 0, 0, 96, 1, 3, 0, 0, 0]);
+        assert_eq!(chunks[1].1, [0, 0, 0, 0x12, 0x34, 0x56]);
+
+        // a stored block of filter byte and 24 bytes per row, then the adler32
+        let zlib = &chunks[2].1;
+        let data = &zlib[7..zlib.len() - 4];
+        assert_eq!(zlib[2], 1);
+        assert_eq!(data.len(), 96 * 25);
+        assert_eq!(u32::from_be_bytes([zlib[zlib.len() - 4], zlib[zlib.len() - 3], zlib[zlib.len() - 2], zlib[zlib.len() - 1]]), adler32(data));
+        // the top left pixel is lit, 3x3 pixels big
+        assert_eq!(&data[..2], [0, 0b1110_0000]);
+        assert_eq!(&data[25 * 3..25 * 3 + 2], [0, 0b0001_1100]);
+    }
+
+    #[test]
+    fn splits_big_images_into_stored_blocks() {
+        let data = vec![7; MAX_BLOCK + 10];
+        let zlib = zlib_stored(&data);
+        assert_eq!(&zlib[2..7], [0, 0xff, 0xff, 0, 0]);
+        let second = 7 + MAX_BLOCK;
+        assert_eq!(&zlib[second..second + 5], [1, 10, 0, 0xf5, 0xff]);
+        assert_eq!(zlib.len(), second + 5 + 10 + 4);
+        assert_eq!(zlib_stored(&[]), [0x78, 0x01, 1, 0, 0, 0xff, 0xff, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn writes_text_and_wrapped_pbm() {
+        let small = screen(3, 2, |x, y| x == y);
+        assert_eq!(small.to_text(), "#..\n.#.\n");
+        assert_eq!(small.to_pbm(), "P1\n3 2\n1 0 0\n0 1 0\n");
+
+        let pbm = screen(128, 64, |x, _| x % 2 == 0).to_pbm();
+        assert!(pbm.lines().all(|line| line.len() <= 70));
+        // a 128 pixels wide row takes 35, 35, 35 and 23 pixels
+        assert_eq!(pbm.lines().count(), 2 + 64 * 4);
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let original = screen(64, 32, |x, y| (x * 7 + y * 3) % 5 == 0);
+        let options = ImageOptions { palette: [[0; 3], [255; 3]], scale: 2 };
+        let png = from_png(&original.to_png(&options)).unwrap();
+        assert_eq!(png.downscale(64, 32).unwrap().pixels, original.pixels);
+        assert_eq!(from_pbm(&original.to_pbm()).unwrap().pixels, original.pixels);
+        assert_eq!(from_text(&original.to_text()).unwrap().pixels, original.pixels);
+        assert_eq!(from_pbm("P1 3 1\n101").unwrap().pixels, [true, false, true]);
+    }
+
+    #[test]
+    fn rejects_truncated_png() {
+        let png = screen(8, 4, |x, _| x == 0).to_png(&ImageOptions { palette: [[0; 3], [255; 3]], scale: 1 });
+        // cut before the end chunk the image data is incomplete, after it it's only missing the end
+        let iend = png.len() - 12;
+        for len in 0..iend {
+            assert!(from_png(&png[..len]).is_err(), "{} bytes", len);
+        }
+        assert!(from_png(&png[..iend]).is_ok());
+        assert!(from_png(&[PNG_SIGNATURE.to_vec(), png[8..33].to_vec()].concat()).is_err());
+    }
+
+    #[test]
+    fn takes_the_screen_in_its_resolution() {
+        let mut chip = Chip8::new
+            ::<&'static (dyn Fn() -> u8 + Send + Sync + 'static),
+            &'static (dyn Fn(u8) -> bool + Send + Sync + 'static)>
+            (&|| 0, &|_| false);
+        // draws the font's 0 at 1, 2
+        chip.load(0x200, &[0x60, 0x00, 0xF0, 0x29, 0x61, 0x01, 0x62, 0x02, 0xD1, 0x25], None);
+        for _ in 0..5 {
+            chip.cpu_tick().unwrap();
+        }
+        let screen = Screenshot::take(&mut chip);
+        assert_eq!((screen.width, screen.height), (64, 32));
+        assert!(screen.get(1, 2) && screen.get(4, 6) && !screen.get(2, 3));
+        // the renderer still has to draw it
+        assert!(chip.display.dirty());
+    }
+}
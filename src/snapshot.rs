@@ -0,0 +1,320 @@
+use chiprust_emu::{Chip8, Chip8State};
+use std::fs;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u16 = 1;
+// the default font lies below, the backend doesn't load programs there
+pub const FONT_END: usize = 240;
+// where the instructions restoring a state run, the memory is loaded over them afterwards
+const SCRATCH: usize = 0x200;
+
+/// The display rows, without taking the redraw away from the renderer like `Display::read` does.
+pub fn read_display(chip: &mut Chip8) -> [u128; 64] {
+    let dirty = chip.display.dirty();
+    let display = *chip.display.read();
+    if dirty {
+        // scrolling by nothing is the only way to mark the display dirty again
+        chip.display.scroll_side(0);
+    }
+    display
+}
+
+/// Copy of the state, `Chip8State` isn't `Clone`.
+pub fn copy_state(state: &Chip8State) -> Chip8State {
+    Chip8State {
+        mem: state.mem.clone(),
+        regs: state.regs,
+        stack: state.stack,
+        pc: state.pc,
+        i: state.i,
+        sp: state.sp,
+        sound_timer: state.sound_timer,
+        delay_timer: state.delay_timer,
+    }
+}
+
+/// Complete machine state, unlike `Chip8State` it includes the display.
+pub struct Snapshot {
+    pub state: Chip8State,
+    pub display: [u128; 64],
+    pub hires: bool,
+}
+
+impl Snapshot {
+    pub fn take(chip: &mut Chip8) -> Snapshot {
+        Snapshot {
+            state: chip.to_state(),
+            display: read_display(chip),
+            hires: chip.display.hi_res(),
+        }
+    }
+
+    /// The backend can only load memory and set the program counter,
+    /// so everything else is set by running instructions that set it.
+    pub fn restore(&self, chip: &mut Chip8) -> Result<(), String> {
+        let state = &self.state;
+        if state.pc < FONT_END || state.pc >= state.mem.len() - 1 {
+            return Err(format!("Can't restore the program counter ${:04x?}.", state.pc));
+        }
+        let stack = &state.stack[1..=state.sp];
+        if let Some(addr) = stack.iter().find(|&&addr| addr < FONT_END || addr >= state.mem.len() - 1) {
+            return Err(format!("Can't restore the return address ${:04x?}.", addr));
+        }
+        let mut font = [0u8; FONT_END];
+        font.copy_from_slice(&state.mem[..FONT_END]);
+        let run = |chip: &mut Chip8, at: usize, code: &[u8]| {
+            chip.load(at, code, Some(font));
+            // none of the instructions used here wait for keys or fail
+            chip.cpu_tick().unwrap();
+        };
+
+        for _ in 0..chip.to_state().sp {
+            run(chip, SCRATCH, &[0x00, 0xEE]);
+        }
+
+        // drawn in hires, where every display row is a sprite row, one byte at a time
+        run(chip, SCRATCH, &[0x00, 0xFF]);
+        run(chip, SCRATCH, &[0x00, 0xE0]);
+        let sprite = SCRATCH + 2;
+        run(chip, SCRATCH, &[0xA0 | (sprite >> 8) as u8, sprite as u8]);
+        for (y, row) in self.display.iter().enumerate() {
+            for x in (0..128).step_by(8) {
+                let byte = (row >> (120 - x)) as u8;
+                if byte != 0 {
+                    run(chip, SCRATCH, &[0x60, x as u8]);
+                    run(chip, SCRATCH, &[0x61, y as u8]);
+                    run(chip, SCRATCH, &[0xD0, 0x11, byte]);
+                }
+            }
+        }
+        if !self.hires {
+            run(chip, SCRATCH, &[0x00, 0xFE]);
+        }
+
+        // a call pushes its own address, so every return address gets a call placed at it
+        for &addr in stack {
+            run(chip, addr, &[0x22, 0x00]);
+        }
+
+        run(chip, SCRATCH, &[0x60, state.delay_timer]);
+        run(chip, SCRATCH, &[0xF0, 0x15]);
+        run(chip, SCRATCH, &[0x60, state.sound_timer]);
+        run(chip, SCRATCH, &[0xF0, 0x18]);
+        let i = state.i.min(0xFFF);
+        run(chip, SCRATCH, &[0xA0 | (i >> 8) as u8, i as u8]);
+        let mut rest = state.i - i;
+        while rest > 0 {
+            let n = rest.min(0xFF);
+            run(chip, SCRATCH, &[0x60, n as u8]);
+            run(chip, SCRATCH, &[0xF0, 0x1E]);
+            rest -= n;
+        }
+        for (x, v) in state.regs.iter().enumerate() {
+            run(chip, SCRATCH, &[0x60 | x as u8, *v]);
+        }
+
+        chip.load(FONT_END, &state.mem[FONT_END..], Some(font));
+        chip.load(state.pc, &state.mem[state.pc..], Some(font));
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(5200);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_be_bytes());
+        bytes.extend_from_slice(&(self.state.pc as u16).to_be_bytes());
+        bytes.extend_from_slice(&(self.state.i as u16).to_be_bytes());
+        bytes.push(self.state.delay_timer);
+        bytes.push(self.state.sound_timer);
+        bytes.extend_from_slice(&self.state.regs);
+        bytes.push(self.hires as u8);
+        // only the entries up to the stack pointer were pushed, the first one never is
+        bytes.push(self.state.sp as u8);
+        for addr in &self.state.stack[1..=self.state.sp] {
+            bytes.extend_from_slice(&(*addr as u16).to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.state.mem[..]);
+        for row in self.display.iter() {
+            bytes.extend_from_slice(&row.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, String> {
+        if bytes.len() < 6 || &bytes[..4] != MAGIC {
+            return Err("Not a save state file.".to_string());
+        }
+        let version = u16::from_be_bytes([bytes[4], bytes[5]]);
+        if version != VERSION {
+            return Err(format!(
+                "Save state version {} is not supported, expected version {}.",
+                version, VERSION
+            ));
+        }
+        let mut reader = Reader { bytes, pos: 6 };
+        let pc = reader.u16()? as usize;
+        let i = reader.u16()? as usize;
+        let delay_timer = reader.take(1)?[0];
+        let sound_timer = reader.take(1)?[0];
+        let mut regs = [0u8; 16];
+        regs.copy_from_slice(reader.take(16)?);
+        let hires = reader.take(1)?[0] != 0;
+        let sp = reader.take(1)?[0] as usize;
+        let mut stack = [0usize; 16];
+        if sp >= stack.len() {
+            return Err(format!("Stack pointer {} is out of the stack.", sp));
+        }
+        for addr in &mut stack[1..=sp] {
+            *addr = reader.u16()? as usize;
+        }
+        let mut mem = Box::new([0u8; 4096]);
+        mem.copy_from_slice(reader.take(4096)?);
+        let mut display = [0u128; 64];
+        for row in display.iter_mut() {
+            let mut row_bytes = [0u8; 16];
+            row_bytes.copy_from_slice(reader.take(16)?);
+            *row = u128::from_be_bytes(row_bytes);
+        }
+        Ok(Snapshot {
+            state: Chip8State {
+                mem,
+                regs,
+                stack,
+                pc,
+                i,
+                sp,
+                sound_timer,
+                delay_timer,
+            },
+            display,
+            hires,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_bytes()).map_err(|e| format!("Can't write {}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Snapshot, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+        Snapshot::from_bytes(&bytes).map_err(|e| format!("Can't load {}: {}", path, e))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.pos + n > self.bytes.len() {
+            return Err("Save state file is truncated.".to_string());
+        }
+        let result = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(result)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &[u8] = &[
+        0xA2, 0x20, 0x60, 0x05, 0x61, 0x03, 0xD0, 0x15, // draw the sprite at 5, 3
+        0xF0, 0x15, 0x63, 0x09, 0xF3, 0x18, 0x22, 0x10, // set the timers, call 0x210
+        0x64, 0x2A, 0x22, 0x14, 0x12, 0x14, 0x00, 0x00, // 0x210: call 0x214, which loops
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xF0, 0x90, 0x90, 0x90, 0xF0,
+    ];
+
+    fn chip(program: &[u8]) -> Chip8 {
+        let mut chip = Chip8::new
+            ::<&'static (dyn Fn() -> u8 + Send + Sync + 'static),
+            &'static (dyn Fn(u8) -> bool + Send + Sync + 'static)>
+            (&|| 0, &|_| false);
+        chip.load(0x200, program, None);
+        chip
+    }
+
+    fn run(chip: &mut Chip8, ticks: usize) {
+        for _ in 0..ticks {
+            chip.cpu_tick().unwrap();
+        }
+    }
+
+    fn assert_same(a: &Snapshot, b: &Snapshot) {
+        let fields = |s: &Snapshot| {
+            let state = &s.state;
+            (
+                state.mem.to_vec(),
+                state.regs,
+                state.stack[..=state.sp].to_vec(),
+                (state.pc, state.i, state.delay_timer, state.sound_timer),
+                s.display.to_vec(),
+                s.hires,
+            )
+        };
+        assert_eq!(fields(a), fields(b));
+    }
+
+    #[test]
+    fn survives_the_file_format() {
+        let mut chip = chip(PROGRAM);
+        run(&mut chip, 9);
+        let snapshot = Snapshot::take(&mut chip);
+        assert_eq!(snapshot.state.sp, 1);
+        let bytes = snapshot.to_bytes();
+        assert_same(&Snapshot::from_bytes(&bytes).unwrap(), &snapshot);
+        assert_eq!(Snapshot::from_bytes(&bytes[..100]).err().unwrap(), "Save state file is truncated.");
+        assert_eq!(Snapshot::from_bytes(b"PNG").err().unwrap(), "Not a save state file.");
+    }
+
+    #[test]
+    fn restores_over_a_running_chip() {
+        let mut chip = chip(PROGRAM);
+        run(&mut chip, 9);
+        let snapshot = Snapshot::take(&mut chip);
+        run(&mut chip, 3);
+        assert_eq!(chip.to_state().sp, 2);
+
+        snapshot.restore(&mut chip).unwrap();
+        assert_same(&Snapshot::take(&mut chip), &snapshot);
+        // the restored machine continues into the nested call
+        run(&mut chip, 2);
+        assert_eq!((chip.get_pc(), chip.to_state().sp), (0x214, 2));
+    }
+
+    #[test]
+    fn restores_lores_and_hires_screens() {
+        for &hires in &[false, true] {
+            let mut original = chip(PROGRAM);
+            if hires {
+                original.display.hi_res_mode();
+            }
+            run(&mut original, 4);
+            let snapshot = Snapshot::take(&mut original);
+            let mut restored = chip(&[]);
+            snapshot.restore(&mut restored).unwrap();
+            assert!(restored.display.dirty());
+            assert_same(&Snapshot::take(&mut restored), &snapshot);
+        }
+    }
+
+    #[test]
+    fn taking_keeps_the_display_dirty() {
+        let mut chip = chip(PROGRAM);
+        run(&mut chip, 4);
+        Snapshot::take(&mut chip);
+        assert!(chip.display.dirty());
+        chip.display.read();
+        Snapshot::take(&mut chip);
+        assert!(!chip.display.dirty());
+    }
+}
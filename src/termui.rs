@@ -1,18 +1,38 @@
 mod drawing;
 
 use chiprust_emu::Chip8State;
+use crate::debugger::Debugger;
 use crossterm::{
     cursor::{Hide, MoveTo},
+    event::{read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+            KeyboardEnhancementFlags, PushKeyboardEnhancementFlags, PopKeyboardEnhancementFlags},
     execute, queue,
-    style::{Colorize, Print, SetBackgroundColor, SetForegroundColor, ResetColor},
-    terminal::{size as terminal_size, EnterAlternateScreen, LeaveAlternateScreen, Clear, ClearType}
+    style::{Color, ContentStyle, Print, Stylize, SetBackgroundColor, SetForegroundColor, ResetColor},
+    terminal::{size as terminal_size, EnterAlternateScreen, LeaveAlternateScreen, Clear, ClearType,
+               enable_raw_mode, disable_raw_mode, supports_keyboard_enhancement}
 };
 use ctrlc::set_handler as set_ctrlc_handler;
+use crate::input;
 use std::io::{stdout, Write};
+use std::sync::{atomic::{AtomicBool, Ordering}, mpsc::{channel, Receiver}};
+use std::thread;
+use std::time::{Duration, Instant};
 
-const MINIMUM_SIZE: (u16, u16) = (143, 36);
+// how long messages like "state saved" stay in the label
+const MESSAGE_DURATION: Duration = Duration::from_secs(3);
+
+const MINIMUM_SIZE: (u16, u16) = (drawing::MINIMUM_WIDTH, 36);
+
+// whether the kitty keyboard protocol flags were pushed and have to be popped on exit
+static KEYBOARD_ENHANCED: AtomicBool = AtomicBool::new(false);
+// typing into the command line doesn't press chip-8 keys
+static COMMAND_LINE_OPEN: AtomicBool = AtomicBool::new(false);
 
 pub fn exit(error_message: &str) {
+    if KEYBOARD_ENHANCED.load(Ordering::Relaxed) {
+        let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+    }
+    let _ = disable_raw_mode();
     execute!(stdout(), LeaveAlternateScreen, ResetColor).expect("Error working with terminal");
     println!("{}", error_message);
     std::process::exit(0)
@@ -21,10 +41,42 @@ pub fn exit(error_message: &str) {
 pub struct TermUI {
     term_size: (u16, u16),
     min_fits: bool,
+    // address selected in the memory panel, follows the pc if none
+    memory_cursor: Option<usize>,
+    // text typed into the command line, if it's open
+    command_line: Option<String>,
+    command_error: Option<String>,
+    message: Option<(String, Instant)>,
+    keys: Receiver<KeyEvent>,
+    screen_style: ContentStyle,
+}
+
+// Reads terminal events on its own thread. The terminal input backend gets every key event outside the command line,
+// so it keeps working while the draw thread waits for the chip, and the ui gets the presses.
+fn read_events() -> Receiver<KeyEvent> {
+    let (sender, receiver) = channel();
+    thread::spawn(move || loop {
+        let key = match read().expect("Error working with terminal") {
+            Event::Key(v) => v,
+            _ => continue,
+        };
+        // releases still go through, so keys held when the command line opened don't stay down
+        if key.kind == KeyEventKind::Release || !COMMAND_LINE_OPEN.load(Ordering::Relaxed) {
+            input::terminal::handle_event(key);
+        }
+        // the draw thread can be stuck behind a chip waiting for a key, don't wait for it to quit
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            exit("")
+        }
+        if key.kind != KeyEventKind::Release && sender.send(key).is_err() {
+            return;
+        }
+    });
+    receiver
 }
 
 impl TermUI {
-    pub fn new() -> TermUI {
+    pub fn new(screen_colors: (Color, Color)) -> TermUI {
         // set up the terminal
         execute!(
             stdout(),
@@ -34,6 +86,20 @@ impl TermUI {
             SetBackgroundColor(drawing::TERMINAL_STYLE.1)
         )
         .expect("Error working with terminal");
+        // raw mode is needed to read hotkeys without waiting for enter
+        enable_raw_mode().expect("Error working with terminal");
+        // ask for key release events, so held keys don't have to be guessed
+        if supports_keyboard_enhancement().unwrap_or(false) {
+            execute!(
+                stdout(),
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                )
+            )
+            .expect("Error working with terminal");
+            KEYBOARD_ENHANCED.store(true, Ordering::Relaxed);
+            input::terminal::set_release_events(true);
+        }
 
         // add a ctrl-c handler to reset the terminal on ctrl-c
         set_ctrlc_handler(|| {
@@ -44,6 +110,10 @@ impl TermUI {
         // add a panic hook to reset the terminal on panic
         // not sure if it should even exist
         std::panic::set_hook(Box::new(|panic_info| {
+            if KEYBOARD_ENHANCED.load(Ordering::Relaxed) {
+                let _ = execute!(stdout(), PopKeyboardEnhancementFlags);
+            }
+            let _ = disable_raw_mode();
             match execute!(stdout(), LeaveAlternateScreen) {
                 Ok(_) => {}
                 Err(e) => {
@@ -71,10 +141,77 @@ impl TermUI {
         TermUI {
             term_size: (0, 0),
             min_fits: false,
+            memory_cursor: None,
+            command_line: None,
+            command_error: None,
+            message: None,
+            keys: read_events(),
+            screen_style: drawing::screen_style(screen_colors),
         }
     }
 
-    pub fn draw(&mut self, label: &str, chip: Chip8State, display: Option<[u128; 64]>) {
+    /// Shows a message in the label for a few seconds.
+    pub fn show_message(&mut self, message: String) {
+        self.message = Some((message, Instant::now()));
+    }
+
+    pub fn open_command_line(&mut self) {
+        self.set_command_line(Some(String::new()));
+        self.command_error = None;
+    }
+
+    fn set_command_line(&mut self, line: Option<String>) {
+        COMMAND_LINE_OPEN.store(line.is_some(), Ordering::Relaxed);
+        self.command_line = line;
+    }
+
+    pub fn is_command_line_open(&self) -> bool {
+        self.command_line.is_some()
+    }
+
+    /// Edits the open command line. Returns the command when enter is pressed.
+    pub fn edit_command_line(&mut self, key: KeyEvent) -> Option<String> {
+        let line = self.command_line.as_mut()?;
+        match key.code {
+            KeyCode::Char(c) => line.push(c),
+            KeyCode::Backspace => {
+                line.pop();
+            }
+            KeyCode::Esc => self.set_command_line(None),
+            KeyCode::Enter => return Some(line.clone()),
+            _ => return None,
+        }
+        self.command_error = None;
+        None
+    }
+
+    /// Closes the command line on success, or keeps it open showing the error.
+    pub fn set_command_result(&mut self, result: Result<(), String>) {
+        match result {
+            Ok(()) => self.set_command_line(None),
+            Err(e) => self.command_error = Some(e),
+        }
+    }
+
+    pub fn cursor(&self, pc: usize) -> usize {
+        self.memory_cursor.unwrap_or(pc)
+    }
+
+    pub fn move_cursor(&mut self, pc: usize, delta: isize) {
+        let cursor = self.cursor(pc) as isize + delta;
+        self.memory_cursor = Some(cursor.clamp(0, 4094) as usize);
+    }
+
+    pub fn reset_cursor(&mut self) {
+        self.memory_cursor = None;
+    }
+
+    /// Returns the next pending key press without blocking.
+    pub fn poll_key(&self) -> Option<KeyEvent> {
+        self.keys.try_recv().ok()
+    }
+
+    pub fn draw(&mut self, label: &str, chip: Chip8State, display: Option<[u128; 64]>, debugger: &Debugger) {
         let mut stdout = stdout();
         if self.term_size != terminal_size().unwrap() {
             queue!(stdout, Clear(ClearType::All)).expect("Error working with terminal");
@@ -93,10 +230,20 @@ impl TermUI {
             drawing::draw_frame(self.term_size, &mut stdout)
         }
         if let Some(d) = display {
-            drawing::draw_screen(&mut stdout, &d)
+            drawing::draw_screen(&mut stdout, &d, self.screen_style)
+        }
+        match (&self.command_line, &self.command_error) {
+            (Some(line), Some(error)) => drawing::draw_label(&mut stdout, &format!(":{} <- {}", line, error)),
+            (Some(line), None) => drawing::draw_label(&mut stdout, &format!(":{}█", line)),
+            (None, _) => match &self.message {
+                Some((message, shown)) if shown.elapsed() < MESSAGE_DURATION => {
+                    drawing::draw_label(&mut stdout, &format!("{}; {}", label, message))
+                }
+                _ => drawing::draw_label(&mut stdout, label),
+            },
         }
-        drawing::draw_label(&mut stdout, label);
-        drawing::draw_memory(self.term_size, &mut stdout, &chip);
+        drawing::draw_memory(self.term_size, &mut stdout, &chip, self.cursor(chip.pc), debugger);
+        drawing::draw_debug_panel(self.term_size, &mut stdout, debugger);
         drawing::draw_regs(self.term_size, &mut stdout, &chip);
         stdout.flush().expect("Error flusing the stdout");
     }
@@ -1,5 +1,7 @@
 use std::hint::unreachable_unchecked;
 use chiprust_emu::{Chip8State, display::get_px, get_opcode};
+use crate::debugger::Debugger;
+use crate::disasm::decode;
 use crossterm::{
     cursor::MoveTo,
     queue,
@@ -15,32 +17,65 @@ const FRAME_HORIZONTAL: &str = "─";
 const DOUBLE_FRAME_VERTICAL: &str = "║";
 const DOUBLE_FRAME_HORIZONTAL: &str = "═";
 
+// layout
+const MEMORY_PANEL_X: u16 = 130;
+const MEMORY_PANEL_WIDTH: u16 = 39;
+// without the mnemonic column, on terminals too narrow for the whole panel
+const MEMORY_PANEL_NARROW_WIDTH: u16 = 22;
+// the screen and at least the narrow memory panel
+pub const MINIMUM_WIDTH: u16 = MEMORY_PANEL_X + MEMORY_PANEL_NARROW_WIDTH;
+const DEBUG_PANEL_X: u16 = MEMORY_PANEL_X + MEMORY_PANEL_WIDTH;
+const DEBUG_PANEL_WIDTH: u16 = 16;
+
 // colors
 pub const TERMINAL_STYLE: (Color, Color) = (Color::White, Color::DarkGrey);
 
-const SCREEN_STYLE: ContentStyle = ContentStyle {
-    foreground_color: Some(Color::White),
-    background_color: Some(Color::DarkGrey),
-    attributes: unsafe { std::mem::transmute(0) },
-};
+// lit and unlit pixels, can be changed with --foreground and --background
+pub fn screen_style(colors: (Color, Color)) -> ContentStyle {
+    ContentStyle {
+        foreground_color: Some(colors.0),
+        background_color: Some(colors.1),
+        underline_color: None,
+        attributes: unsafe { std::mem::transmute(0) },
+    }
+}
+
 const BORDER_STYLE: ContentStyle = ContentStyle {
     foreground_color: Some(Color::DarkGrey),
     background_color: Some(Color::White),
+    underline_color: None,
     attributes: unsafe { std::mem::transmute(0) },
 };
 const MEMORY_DEBUG_STYLE: ContentStyle = ContentStyle {
     foreground_color: Some(Color::DarkGrey),
     background_color: Some(Color::Grey),
+    underline_color: None,
     attributes: unsafe { std::mem::transmute(0) },
 };
 const MEMORY_CURRENT_STYLE: ContentStyle = ContentStyle {
     foreground_color: Some(Color::Black),
     background_color: Some(Color::White),
+    underline_color: None,
     attributes: unsafe { std::mem::transmute(0) },
 };
+fn memory_cursor_style() -> ContentStyle {
+    ContentStyle {
+        foreground_color: Some(Color::White),
+        background_color: Some(Color::DarkBlue),
+        ..ContentStyle::new()
+    }
+}
+fn memory_breakpoint_style() -> ContentStyle {
+    ContentStyle {
+        foreground_color: Some(Color::DarkRed),
+        background_color: Some(Color::Grey),
+        ..ContentStyle::new()
+    }
+}
 const REGISTER_DEBUG_STYLE: ContentStyle = ContentStyle {
     foreground_color: Some(Color::White),
     background_color: Some(Color::DarkGrey),
+    underline_color: None,
     attributes: unsafe { std::mem::transmute(0) },
 };
 
@@ -187,47 +222,125 @@ pub fn draw_frame(term_size: (u16, u16), stdout: &mut std::io::Stdout) {
     // # Draw memory block delimiter
     draw_vertical_delimiter(stdout, term_size, 129, 0, term_size.1);
     // # Draw register block delimiter
-    draw_horizontal_delimiter(stdout, term_size, 0, 33, 130)
+    draw_horizontal_delimiter(stdout, term_size, 0, 33, 130);
+    // # Draw debug block delimiter
+    if debug_panel_fits(term_size) {
+        draw_vertical_delimiter(stdout, term_size, DEBUG_PANEL_X - 1, 0, term_size.1);
+    }
+}
+
+fn debug_panel_fits(term_size: (u16, u16)) -> bool {
+    term_size.0 > DEBUG_PANEL_X + DEBUG_PANEL_WIDTH
 }
 
-pub fn draw_screen(stdout: &mut std::io::Stdout, display: &[u128; 64]) {
+pub fn draw_screen(stdout: &mut std::io::Stdout, display: &[u128; 64], style: ContentStyle) {
     for (i, row) in get_screen(display).iter().enumerate() {
         queue!(
             stdout,
             MoveTo(1, i as u16 + 1),
-            Print(SCREEN_STYLE.apply(row))
+            Print(style.apply(row))
         )
         .expect("Error working with terminal");
     }
 }
 
-pub fn draw_memory(term_size: (u16, u16), stdout: &mut std::io::Stdout, chip: &Chip8State) {
+pub fn draw_memory(
+    term_size: (u16, u16),
+    stdout: &mut std::io::Stdout,
+    chip: &Chip8State,
+    cursor: usize,
+    debugger: &Debugger,
+) {
     let number_of_entries = term_size.1 - 2;
     let current_pos = number_of_entries / 2;
-    let starting_with = chip.pc + current_pos as usize - number_of_entries as usize;
+    let starting_with = (cursor + current_pos as usize).saturating_sub(number_of_entries as usize);
+    // narrow terminals drop the mnemonics, so the pixel visualisation stays on screen
+    let wide = term_size.0 > MEMORY_PANEL_X + MEMORY_PANEL_WIDTH;
+    let panel_width = if wide { MEMORY_PANEL_WIDTH } else { MEMORY_PANEL_NARROW_WIDTH };
+    let width = (panel_width - 1).min(term_size.0 - MEMORY_PANEL_X - 1) as usize;
     for i in 1..=number_of_entries as usize {
-        queue!(stdout, MoveTo(130, i as u16)).expect("Error working with terminal");
-        if i == current_pos as usize {
-            queue!(
-                stdout,
-                Print(MEMORY_CURRENT_STYLE.apply(format!(
-                    " ${:04x?}: {:04x?}; {}",
-                    starting_with + i,
-                    get_opcode(&chip.mem, starting_with + i),
-                    get_visual_double_byte(get_opcode(&chip.mem, starting_with + i))
-                )))
-            )
+        let addr = starting_with + i;
+        queue!(stdout, MoveTo(MEMORY_PANEL_X, i as u16)).expect("Error working with terminal");
+        if addr >= chip.mem.len() - 1 {
+            queue!(stdout, Print(MEMORY_DEBUG_STYLE.apply(" ".repeat(width))))
+                .expect("Error working with terminal");
+            continue;
+        }
+        let opcode = get_opcode(&chip.mem, addr);
+        let mnemonic = match decode(&chip.mem[..], addr) {
+            Some(instruction) => instruction.to_string(),
+            None => "???".to_string(),
+        };
+        let is_breakpoint = debugger.breakpoints.contains(addr);
+        let is_watched = debugger.watchpoints.contains(addr);
+        let marker = if is_breakpoint {
+            '●'
+        } else if is_watched {
+            '○'
+        } else {
+            ' '
+        };
+        let (style, current) = if addr == chip.pc {
+            (MEMORY_CURRENT_STYLE, '$')
+        } else if addr == cursor {
+            (memory_cursor_style(), ' ')
+        } else if is_breakpoint || is_watched {
+            (memory_breakpoint_style(), ' ')
+        } else {
+            (MEMORY_DEBUG_STYLE, ' ')
+        };
+        let mnemonic = if wide { format!("{:<16.16}", mnemonic) } else { String::new() };
+        let line = format!(
+            "{}{}{:04x?}: {:04x?} {}{}",
+            marker,
+            current,
+            addr,
+            opcode,
+            mnemonic,
+            get_visual_double_byte(opcode)
+        );
+        let line: String = line.chars().take(width).collect();
+        queue!(stdout, Print(style.apply(line))).expect("Error working with terminal");
+    }
+}
+
+pub fn draw_debug_panel(term_size: (u16, u16), stdout: &mut std::io::Stdout, debugger: &Debugger) {
+    if !debug_panel_fits(term_size) {
+        return;
+    }
+    let width = (term_size.0 - DEBUG_PANEL_X - 1) as usize;
+    let mut lines = vec![format!(" Breakpoints ({})", debugger.breakpoints.iter().count())];
+    lines.extend(
+        debugger
+            .breakpoints
+            .iter()
+            .map(|(addr, hits)| format!(" ${:04x?} hits: {}", addr, hits)),
+    );
+    lines.push(String::new());
+    lines.push(format!(" Watchpoints ({})", debugger.watchpoints.iter().count()));
+    lines.extend(debugger.watchpoints.iter().map(|range| {
+        if range.len() == 1 {
+            format!(" ${:04x?}", range.start)
         } else {
-            queue!(
-                stdout,
-                Print(MEMORY_DEBUG_STYLE.apply(format!(
-                    " {:04x?}: {:04x?};  {}",
-                    starting_with + i,
-                    get_opcode(&chip.mem, starting_with + i),
-                    get_visual_double_byte(get_opcode(&chip.mem, starting_with + i))
-                )))
-            )
+            format!(" ${:04x?}..${:04x?}", range.start, range.end)
         }
+    }));
+    lines.push(String::new());
+    lines.push(format!(" Conditions ({})", debugger.conditions.len()));
+    lines.extend(
+        debugger
+            .conditions
+            .iter()
+            .enumerate()
+            .map(|(n, c)| format!(" #{} {} hits: {}", n + 1, c.source, c.hits)),
+    );
+    for y in 1..term_size.1 - 1 {
+        let line = lines.get(y as usize - 1).map(|l| l.as_str()).unwrap_or("");
+        queue!(
+            stdout,
+            MoveTo(DEBUG_PANEL_X, y),
+            Print(REGISTER_DEBUG_STYLE.apply(format!("{:<width$.width$}", line, width = width)))
+        )
         .expect("Error working with terminal");
     }
 }
@@ -265,10 +378,11 @@ pub fn draw_regs(term_size: (u16, u16), stdout: &mut std::io::Stdout, chip: &Chi
 }
 
 pub fn draw_label(stdout: &mut std::io::Stdout, label: &str) {
+    // pad with the frame so a shorter label overwrites a longer one
     queue!(
         stdout,
         MoveTo(2, 0),
-        Print(BORDER_STYLE.apply(label))
+        Print(BORDER_STYLE.apply(format!("{:─<127.127}", label)))
     )
     .expect("Error working with terminal");
 }